@@ -1,22 +1,191 @@
 use std::env;
+use std::fs;
+use std::path::Path;
 
 const LIBCAPNG_LIB_NAME: &str = "cap-ng";
+const LIBCAPNG_PKG_NAME: &str = "libcap-ng";
 const LIBCAPNG_LIB_PATH: &str = "LIBCAPNG_LIB_PATH";
 const LIBCAPNG_LINK_TYPE: &str = "LIBCAPNG_LINK_TYPE";
 
+/// The crate's capability table, which the metadata table, the `cap`
+/// constants and the name lookup table are generated from.
+const CAPABILITY_TABLE: &str = "src/capabilities.txt";
+
+/// A line of the capability table.
+struct CapabilityEntry {
+    id: u32,
+    constant: String,
+    category: String,
+    since: String,
+    description: String,
+}
+
+impl CapabilityEntry {
+    /// The name as used by libcap-ng, e.g. `net_admin`.
+    fn name(&self) -> String {
+        self.constant.to_ascii_lowercase()
+    }
+}
+
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={}", CAPABILITY_TABLE);
     println!("cargo:rerun-if-env-changed={}", LIBCAPNG_LIB_PATH);
     println!("cargo:rerun-if-env-changed={}", LIBCAPNG_LINK_TYPE);
 
+    let link_type = match env::var(LIBCAPNG_LINK_TYPE) {
+        Ok(val) if matches!(val.as_str(), "dylib" | "static") => val,
+        _ => String::from("dylib"),
+    };
+
     if let Ok(path) = env::var(LIBCAPNG_LIB_PATH) {
         println!("cargo:rustc-link-search=native={}", path);
+    } else {
+        probe_pkg_config(link_type == "static");
     }
 
-    let link_type = match env::var(LIBCAPNG_LINK_TYPE) {
-            Ok(val) if matches!(val.as_str(), "dylib" | "static") => val,
-            _ => String::from("dylib"),
-    };
+    // Symbols from a static libcap-ng can't be looked up at runtime, so let
+    // the crate bind the optional ones directly.
+    println!("cargo:rustc-check-cfg=cfg(capng_static_link)");
+    if link_type == "static" {
+        println!("cargo:rustc-cfg=capng_static_link");
+    }
 
     println!("cargo:rustc-link-lib={}={}", link_type, LIBCAPNG_LIB_NAME);
+
+    let capabilities = read_capabilities();
+    generate_capabilities(&capabilities);
+    generate_name_table(&capabilities);
+}
+
+/// Parses the capability table, skipping blank lines and `#` comments.
+fn read_capabilities() -> Vec<CapabilityEntry> {
+    let table = fs::read_to_string(CAPABILITY_TABLE).expect("failed to read the capability table");
+    let capabilities: Vec<CapabilityEntry> = table
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let fields: Vec<&str> = line.splitn(5, ' ').collect();
+            match fields[..] {
+                [id, constant, category, since, description] => CapabilityEntry {
+                    id: id.parse().expect("invalid capability ID"),
+                    constant: constant.to_string(),
+                    category: category.to_string(),
+                    since: since.to_string(),
+                    description: description.to_string(),
+                },
+                _ => panic!("malformed capability table line: {}", line),
+            }
+        })
+        .collect();
+
+    // The metadata table is indexed by ID.
+    for (index, cap) in capabilities.iter().enumerate() {
+        assert_eq!(cap.id as usize, index, "capability IDs must be consecutive");
+    }
+
+    capabilities
+}
+
+/// Writes `capabilities.rs` to the output directory, holding the metadata
+/// table `capability_info` answers from, and the `consts` module re-exported
+/// as `cap`.
+fn generate_capabilities(capabilities: &[CapabilityEntry]) {
+    let mut code = String::from(
+        "/// Metadata for every capability, indexed by capability ID.\n\
+         const CAPABILITIES: &[CapabilityInfo] = &[\n",
+    );
+    for cap in capabilities {
+        code.push_str(&format!(
+            "    CapabilityInfo {{\n        name: {:?},\n        category: Category::{},\n        \
+             description: {:?},\n        since_kernel: {:?},\n    }},\n",
+            cap.name(),
+            cap.category,
+            cap.description,
+            cap.since
+        ));
+    }
+    code.push_str("];\n\n#[cfg(test)]\nconst IDS: &[Capability] = &[");
+    for cap in capabilities {
+        code.push_str(&format!("{}, ", cap.id));
+    }
+    code.push_str(
+        "];\n\n/// The ID of every capability known to the crate, named like `CAP_*`\n\
+         /// without the prefix, e.g. `cap::NET_ADMIN`.\n\
+         pub mod consts {\n    use crate::Capability;\n\n",
+    );
+    for cap in capabilities {
+        code.push_str(&format!(
+            "    /// {}\n    pub const {}: Capability = {};\n",
+            cap.description, cap.constant, cap.id
+        ));
+    }
+    code.push_str("}\n");
+
+    write_generated("capabilities.rs", code);
+}
+
+/// Writes `names_table.rs` to the output directory, holding a perfect hash
+/// map from the lowercase names in the crate's capability table to their
+/// IDs, for `lookup_name_fast`.
+fn generate_name_table(capabilities: &[CapabilityEntry]) {
+    let names: Vec<(String, String)> = capabilities
+        .iter()
+        .map(|cap| (cap.name(), cap.id.to_string()))
+        .collect();
+
+    let mut map = phf_codegen::Map::new();
+    for (name, id) in &names {
+        map.entry(name.as_str(), id);
+    }
+
+    write_generated(
+        "names_table.rs",
+        format!(
+            "static NAME_TABLE: phf::Map<&'static str, Capability> = {};\n",
+            map.build()
+        ),
+    );
+}
+
+/// Writes `code` to `file` in the output directory.
+fn write_generated(file: &str, code: String) {
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR not set");
+    fs::write(Path::new(&out_dir).join(file), code)
+        .unwrap_or_else(|e| panic!("failed to write {}: {}", file, e));
+}
+
+/// Looks libcap-ng up through pkg-config, adding its library directories to
+/// the search path.
+///
+/// The pkg-config crate honors `PKG_CONFIG_SYSROOT_DIR` and the
+/// target-specific `PKG_CONFIG_PATH_<target>` and `PKG_CONFIG_LIBDIR_<target>`
+/// variables, so when cross-compiling the target's library is found instead
+/// of the host's. It also refuses to use pkg-config for a cross build unless
+/// it's been configured for it, in which case we fall back to the linker's
+/// default search path, as we do if libcap-ng isn't known to pkg-config.
+fn probe_pkg_config(statik: bool) {
+    match pkg_config::Config::new()
+        .statik(statik)
+        .cargo_metadata(false)
+        .env_metadata(true)
+        .probe(LIBCAPNG_PKG_NAME)
+    {
+        Ok(lib) => {
+            for path in lib.link_paths {
+                println!("cargo:rustc-link-search=native={}", path.display());
+            }
+        }
+        Err(e) => {
+            if env::var("TARGET").ok() != env::var("HOST").ok() {
+                println!(
+                    "cargo:warning=libcap-ng not found through pkg-config, set {} or \
+                     PKG_CONFIG_SYSROOT_DIR to link the target's library: {}",
+                    LIBCAPNG_LIB_PATH,
+                    e.to_string().lines().next().unwrap_or_default()
+                );
+            }
+        }
+    }
 }
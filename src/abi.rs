@@ -0,0 +1,131 @@
+// Copyright (C) 2020 Red Hat, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+//! The layout of the `capget`/`capset` syscall arguments, for interop with
+//! code that calls them directly rather than going through libcap-ng.
+
+use std::os::raw::c_int;
+
+use crate::{CapState, CapabilitySet};
+
+/// 32-bit capabilities, with a single `CapUserData`.
+pub const LINUX_CAPABILITY_VERSION_1: u32 = 0x1998_0330;
+/// 64-bit capabilities, with two `CapUserData`. Deprecated by the kernel in
+/// favor of version 3, which has the same layout.
+pub const LINUX_CAPABILITY_VERSION_2: u32 = 0x2007_1026;
+/// 64-bit capabilities, with two `CapUserData`.
+pub const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// The kernel's `struct __user_cap_header_struct`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CapUserHeader {
+    pub version: u32,
+    pub pid: c_int,
+}
+
+/// The kernel's `struct __user_cap_data_struct`, holding 32 capabilities of
+/// each set: the first element holds capabilities 0 to 31, the second one
+/// capabilities 32 to 63.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CapUserData {
+    pub effective: u32,
+    pub permitted: u32,
+    pub inheritable: u32,
+}
+
+/// Builds a `CapState` from the arguments of `capget`, as filled in by the
+/// kernel.
+///
+/// Only as many elements of `data` as `header.version` provides for are
+/// read, or all of them for an unknown version. The bounding and ambient sets
+/// aren't part of these, so they're left empty.
+pub fn from_cap_user_data(header: &CapUserHeader, data: &[CapUserData]) -> CapState {
+    let words = if header.version == LINUX_CAPABILITY_VERSION_1 {
+        1
+    } else {
+        2
+    };
+    let set = |word: fn(&CapUserData) -> u32| {
+        let bits = data
+            .iter()
+            .take(words)
+            .enumerate()
+            .fold(0u64, |bits, (i, d)| bits | u64::from(word(d)) << (32 * i));
+        CapabilitySet::from_bits(bits)
+    };
+
+    CapState {
+        effective: set(|d| d.effective),
+        permitted: set(|d| d.permitted),
+        inheritable: set(|d| d.inheritable),
+        ..CapState::default()
+    }
+}
+
+/// Builds the arguments of `capset` for giving the process `pid` the
+/// effective, permitted and inheritable sets of `state`, using the version 3
+/// layout. The bounding and ambient sets can't be set this way, so they're
+/// ignored.
+pub fn to_cap_user_data(state: &CapState, pid: c_int) -> (CapUserHeader, [CapUserData; 2]) {
+    let word = |i: u32| CapUserData {
+        effective: (state.effective.bits() >> (32 * i)) as u32,
+        permitted: (state.permitted.bits() >> (32 * i)) as u32,
+        inheritable: (state.inheritable.bits() >> (32 * i)) as u32,
+    };
+
+    (
+        CapUserHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid,
+        },
+        [word(0), word(1)],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cap_user_data_tests() {
+        let state = CapState {
+            effective: [0, 5, 40].iter().collect(),
+            permitted: [0, 5, 31, 32, 40].iter().collect(),
+            inheritable: [63].iter().collect(),
+            ..CapState::default()
+        };
+
+        let (header, data) = to_cap_user_data(&state, 0);
+        assert_eq!(header.version, LINUX_CAPABILITY_VERSION_3);
+        assert_eq!(data[0].permitted, 1 << 31 | 1 << 5 | 1);
+        assert_eq!(data[1].permitted, 1 << 8 | 1);
+        assert_eq!(data[1].inheritable, 1 << 31);
+        assert_eq!(from_cap_user_data(&header, &data), state);
+
+        let v1 = CapUserHeader {
+            version: LINUX_CAPABILITY_VERSION_1,
+            pid: 0,
+        };
+        let low = from_cap_user_data(&v1, &data);
+        assert_eq!(low.permitted.iter().collect::<Vec<_>>(), vec![0, 5, 31]);
+        assert!(low.inheritable.is_empty());
+
+        // The kernel fills these in the same way for the calling thread.
+        let mut header = CapUserHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let mut data = [CapUserData::default(); 2];
+        // Safe because header and data are valid for the v3 layout.
+        let ret = unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) };
+        assert_eq!(ret, 0);
+        let actual = CapState::capture().unwrap();
+        let state = from_cap_user_data(&header, &data);
+        assert_eq!(state.effective, actual.effective);
+        assert_eq!(state.permitted, actual.permitted);
+        assert_eq!(state.inheritable, actual.inheritable);
+    }
+}
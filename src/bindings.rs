@@ -50,3 +50,11 @@ extern "C" {
     pub fn capng_save_state() -> *mut ::std::os::raw::c_void;
     pub fn capng_restore_state(state: *mut *mut ::std::os::raw::c_void);
 }
+
+// Introduced in libcap-ng 0.8. Resolved at runtime unless linking statically.
+#[cfg(capng_static_link)]
+#[link(name = "cap-ng")]
+extern "C" {
+    pub fn capng_get_rootid() -> ::std::os::raw::c_int;
+    pub fn capng_set_rootid(rootid: ::std::os::raw::c_int) -> ::std::os::raw::c_int;
+}
@@ -0,0 +1,237 @@
+// Copyright (C) 2020 Red Hat, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+use std::fmt;
+use std::iter::FromIterator;
+
+use crate::{capabilities_in, capability_info, capability_to_name, Capability, Type};
+
+/// Number of capabilities a `CapabilitySet` can hold, matching the two
+/// 32-bit words the kernel uses for each capability vector.
+const MAX_CAPABILITIES: Capability = 64;
+
+/// A set of capabilities, stored as a bitmask indexed by capability ID.
+///
+/// Unlike the libcap-ng state, this is a plain value: building, comparing or
+/// combining sets never touches the process capabilities.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CapabilitySet {
+    bits: u64,
+}
+
+impl CapabilitySet {
+    pub const fn empty() -> Self {
+        CapabilitySet { bits: 0 }
+    }
+
+    /// Builds a set from a mask where bit N stands for capability N.
+    pub const fn from_bits(bits: u64) -> Self {
+        CapabilitySet { bits }
+    }
+
+    pub const fn bits(self) -> u64 {
+        self.bits
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.bits == 0
+    }
+
+    pub const fn len(self) -> usize {
+        self.bits.count_ones() as usize
+    }
+
+    pub fn contains(self, capability: Capability) -> bool {
+        capability < MAX_CAPABILITIES && self.bits & (1 << capability) != 0
+    }
+
+    /// Adds `capability` to the set, returning whether it wasn't already
+    /// present. IDs beyond what the kernel can represent are ignored.
+    pub fn insert(&mut self, capability: Capability) -> bool {
+        if capability >= MAX_CAPABILITIES || self.contains(capability) {
+            return false;
+        }
+        self.bits |= 1 << capability;
+        true
+    }
+
+    /// Removes `capability` from the set, returning whether it was present.
+    pub fn remove(&mut self, capability: Capability) -> bool {
+        if !self.contains(capability) {
+            return false;
+        }
+        self.bits &= !(1 << capability);
+        true
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        CapabilitySet {
+            bits: self.bits | other.bits,
+        }
+    }
+
+    pub const fn intersection(self, other: Self) -> Self {
+        CapabilitySet {
+            bits: self.bits & other.bits,
+        }
+    }
+
+    pub const fn difference(self, other: Self) -> Self {
+        CapabilitySet {
+            bits: self.bits & !other.bits,
+        }
+    }
+
+    /// Iterates over the capabilities in the set, in ascending order.
+    pub fn iter(self) -> Iter {
+        Iter { bits: self.bits }
+    }
+
+    /// Iterates over the names of the capabilities in the set, like
+    /// `"net_admin"`, in ascending order of ID, without allocating.
+    ///
+    /// Names come from the crate's own table, see `capability_info`, so
+    /// capabilities unknown to the crate are skipped.
+    pub fn iter_names(self) -> impl Iterator<Item = &'static str> {
+        self.iter()
+            .filter_map(|cap| capability_info(cap).map(|info| info.name))
+    }
+
+    /// Collects the `which` vector of the current libcap-ng state.
+    pub(crate) fn from_state(which: Type) -> Self {
+        capabilities_in(which).into_iter().collect()
+    }
+}
+
+impl fmt::Debug for CapabilitySet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl fmt::Display for CapabilitySet {
+    /// Displays the set as comma-separated names, like `cap_chown,cap_kill`,
+    /// as `getpcaps` does.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, cap) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            match capability_to_name(cap) {
+                Ok(name) => write!(f, "cap_{}", name)?,
+                Err(_) => write!(f, "{}", cap)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromIterator<Capability> for CapabilitySet {
+    fn from_iter<I: IntoIterator<Item = Capability>>(iter: I) -> Self {
+        let mut set = CapabilitySet::empty();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<'a> FromIterator<&'a Capability> for CapabilitySet {
+    fn from_iter<I: IntoIterator<Item = &'a Capability>>(iter: I) -> Self {
+        iter.into_iter().copied().collect()
+    }
+}
+
+impl Extend<Capability> for CapabilitySet {
+    fn extend<I: IntoIterator<Item = Capability>>(&mut self, iter: I) {
+        for capability in iter {
+            self.insert(capability);
+        }
+    }
+}
+
+impl IntoIterator for CapabilitySet {
+    type Item = Capability;
+    type IntoIter = Iter;
+
+    fn into_iter(self) -> Iter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for &CapabilitySet {
+    type Item = Capability;
+    type IntoIter = Iter;
+
+    fn into_iter(self) -> Iter {
+        self.iter()
+    }
+}
+
+/// Iterator over the capabilities in a `CapabilitySet`.
+#[derive(Clone, Debug)]
+pub struct Iter {
+    bits: u64,
+}
+
+impl Iterator for Iter {
+    type Item = Capability;
+
+    fn next(&mut self) -> Option<Capability> {
+        if self.bits == 0 {
+            return None;
+        }
+        let capability = self.bits.trailing_zeros();
+        self.bits &= self.bits - 1;
+        Some(capability)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.bits.count_ones() as usize;
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for Iter {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capability_set_tests() {
+        let mut set: CapabilitySet = [5, 0, 12].iter().collect();
+        assert_eq!(set.len(), 3);
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![0, 5, 12]);
+        assert_eq!(set.bits(), 1 | 1 << 5 | 1 << 12);
+
+        assert!(!set.insert(5));
+        assert!(set.insert(63));
+        assert!(!set.insert(64));
+        assert!(!set.contains(64));
+        assert!(set.remove(63));
+        assert!(!set.remove(63));
+
+        let other: CapabilitySet = [0, 1].iter().collect();
+        assert_eq!(
+            set.union(other).iter().collect::<Vec<_>>(),
+            vec![0, 1, 5, 12]
+        );
+        assert_eq!(set.intersection(other).iter().collect::<Vec<_>>(), vec![0]);
+        assert_eq!(
+            set.difference(other).iter().collect::<Vec<_>>(),
+            vec![5, 12]
+        );
+        assert!(CapabilitySet::empty().is_empty());
+        assert_eq!(format!("{:?}", other), "{0, 1}");
+    }
+
+    #[test]
+    fn iter_names_tests() {
+        let set: CapabilitySet = [12, 0, 63].iter().collect();
+        assert_eq!(
+            set.iter_names().collect::<Vec<_>>().join(","),
+            "chown,net_admin"
+        );
+        assert_eq!(CapabilitySet::empty().iter_names().next(), None);
+    }
+}
@@ -0,0 +1,703 @@
+// Copyright (C) 2020 Red Hat, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+
+use crate::{
+    apply_caps_raw_fd, capability_to_name, clear, get_caps_process, get_caps_raw_fd, update,
+    with_saved_state, Action, CUpdate, Capability, CapabilitySet, Error, Result, Set, Type,
+};
+
+/// A snapshot of the capabilities stored in a file's extended attributes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FileCaps {
+    pub permitted: CapabilitySet,
+    pub inheritable: CapabilitySet,
+    /// The capabilities raised in the effective set on exec. Depending on
+    /// the file's effective bit, these are either all of the permitted and
+    /// inheritable capabilities, or none.
+    pub effective: CapabilitySet,
+}
+
+impl FileCaps {
+    /// Loads the file capabilities held in the current libcap-ng state, as
+    /// left by `get_caps_file`.
+    fn load_state(&mut self) {
+        self.permitted = CapabilitySet::from_state(Type::PERMITTED);
+        self.inheritable = CapabilitySet::from_state(Type::INHERITABLE);
+        self.effective = CapabilitySet::from_state(Type::EFFECTIVE);
+    }
+
+    /// Returns the vector named by `which`, which must be a single vector.
+    pub fn get(&self, which: Type) -> CapabilitySet {
+        match which {
+            Type::EFFECTIVE => self.effective,
+            Type::PERMITTED => self.permitted,
+            Type::INHERITABLE => self.inheritable,
+            _ => CapabilitySet::empty(),
+        }
+    }
+}
+
+/// Reads the file capabilities of `file` into an owned `FileCaps`.
+///
+/// Unlike `get_caps_file`, which loads them into the libcap-ng state, this
+/// saves the state before reading and restores it afterwards, so inspecting
+/// a file never disturbs process capabilities being configured meanwhile.
+pub fn read_file_caps(file: &File) -> Result<FileCaps> {
+    with_saved_state(|| {
+        get_caps_raw_fd(file.as_raw_fd())?;
+        let mut caps = FileCaps::default();
+        caps.load_state();
+        Ok(caps)
+    })
+}
+
+/// Iterates over the capabilities in the `which` vectors of the file
+/// capabilities of `file`, by increasing ID.
+///
+/// The capabilities are read with `read_file_caps`, so the libcap-ng state
+/// is left untouched, and the returned iterator owns them.
+pub fn file_caps_iter(file: &File, which: Type) -> Result<impl Iterator<Item = Capability>> {
+    let caps = read_file_caps(file)?;
+
+    Ok(which
+        .into_iter()
+        .fold(CapabilitySet::empty(), |set, t| set.union(caps.get(t)))
+        .into_iter())
+}
+
+/// Lists the capabilities requested by the file capabilities of `file`, in
+/// its permitted or inheritable sets, that aren't in `allowed`, sorted by ID.
+///
+/// This flags binaries requesting more than a policy permits. An empty
+/// vector means the file complies. The libcap-ng state is left untouched.
+pub fn lint_file_caps(file: &File, allowed: &CapabilitySet) -> Result<Vec<Capability>> {
+    let caps = read_file_caps(file)?;
+
+    Ok(caps
+        .permitted
+        .union(caps.inheritable)
+        .difference(*allowed)
+        .iter()
+        .collect())
+}
+
+/// The extended attribute holding file capabilities.
+const CAPS_XATTR: &[u8] = b"security.capability\0";
+/// The effective bit in the first word of the extended attribute, see
+/// `VFS_CAP_FLAGS_EFFECTIVE` in the kernel.
+const VFS_CAP_FLAGS_EFFECTIVE: u32 = 0x0000_0001;
+
+/// Returns whether the effective bit of the file capabilities of `file` is
+/// set, like `getcap` tells `=ep` apart from `=p`.
+///
+/// The bit decides whether the permitted and inheritable capabilities the
+/// file grants are raised in the effective set on exec, or left for the
+/// program to raise. A file without capabilities gives `false`. The
+/// extended attribute is read directly, so the libcap-ng state is left
+/// untouched.
+pub fn file_caps_effective_bit(file: &File) -> Result<bool> {
+    let fd = file.as_raw_fd();
+    // Big enough for every format version, the biggest being the one with a
+    // root ID, see `struct vfs_ns_cap_data` in the kernel.
+    let mut data = [0u8; 24];
+
+    // Safe because the name is nul-terminated and the kernel writes at most
+    // the size of data into it.
+    let ret = unsafe {
+        libc::fgetxattr(
+            fd,
+            CAPS_XATTR.as_ptr() as *const libc::c_char,
+            data.as_mut_ptr() as *mut libc::c_void,
+            data.len(),
+        )
+    };
+    if ret < 0 {
+        return match std::io::Error::last_os_error().raw_os_error() {
+            Some(libc::ENODATA) => Ok(false),
+            _ => Err(Error::GetCapsFd(fd)),
+        };
+    }
+    if ret < 4 {
+        return Err(Error::GetCapsFd(fd));
+    }
+
+    let magic_etc = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    Ok(magic_etc & VFS_CAP_FLAGS_EFFECTIVE != 0)
+}
+
+/// Returns whether `fd` has the extended attribute holding file
+/// capabilities.
+///
+/// The attribute is queried directly, so `errno` is read right after the
+/// call. It can't be relied upon once a libcap-ng wrapper returned, as its
+/// tracing span may have clobbered it on exit.
+fn has_caps_xattr(fd: RawFd) -> std::io::Result<bool> {
+    // Safe because the name is nul-terminated, and a null buffer of size 0
+    // only queries the size of the attribute.
+    let ret = unsafe {
+        libc::fgetxattr(
+            fd,
+            CAPS_XATTR.as_ptr() as *const libc::c_char,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if ret >= 0 {
+        return Ok(true);
+    }
+
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::ENODATA) => Ok(false),
+        _ => Err(err),
+    }
+}
+
+/// Whether a file carries file capabilities, see `read_file_caps_status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileCapsStatus {
+    /// The file has capabilities, as read.
+    Present(FileCaps),
+    /// The file has no capabilities.
+    Absent,
+}
+
+/// Reads the file capabilities of `file`, telling a file without any apart
+/// from one whose capabilities couldn't be read.
+///
+/// Unlike `read_file_caps`, a file without capabilities, or whose
+/// capabilities are all empty, gives `FileCapsStatus::Absent` rather than an
+/// error. Only actual failures, like I/O or permission errors, are reported
+/// as `Error::GetCapsFd`. The libcap-ng state is left untouched.
+pub fn read_file_caps_status(file: &File) -> Result<FileCapsStatus> {
+    let fd = file.as_raw_fd();
+    if !has_caps_xattr(fd).map_err(|_| Error::GetCapsFd(fd))? {
+        return Ok(FileCapsStatus::Absent);
+    }
+
+    with_saved_state(|| {
+        get_caps_raw_fd(fd)?;
+
+        let mut caps = FileCaps::default();
+        caps.load_state();
+        if caps == FileCaps::default() {
+            Ok(FileCapsStatus::Absent)
+        } else {
+            Ok(FileCapsStatus::Present(caps))
+        }
+    })
+}
+
+/// The difference between the capabilities requested by a file and the
+/// ones permitted to the process, see `diff_file_vs_process`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CapDiff {
+    /// Requested by the file, but not permitted to the process, which
+    /// therefore can't grant them.
+    pub missing: CapabilitySet,
+    /// Permitted to the process, but not requested by the file.
+    pub extra: CapabilitySet,
+}
+
+impl CapDiff {
+    /// Returns whether the process permits exactly what the file requests.
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+impl fmt::Display for CapDiff {
+    /// Displays the difference like `missing: net_admin, sys_time; extra: none`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = |set: CapabilitySet| {
+            if set.is_empty() {
+                return "none".to_string();
+            }
+            set.iter()
+                .map(|cap| capability_to_name(cap).unwrap_or_else(|_| cap.to_string()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        write!(
+            f,
+            "missing: {}; extra: {}",
+            names(self.missing),
+            names(self.extra)
+        )
+    }
+}
+
+/// Compares the capabilities requested by the file capabilities of `file`,
+/// in its permitted or inheritable sets, with the permitted set of the
+/// target process.
+///
+/// This tells whether the process could grant everything the file requests,
+/// and what it lacks. The libcap-ng state is restored before returning.
+pub fn diff_file_vs_process(file: &File) -> Result<CapDiff> {
+    with_saved_state(|| {
+        get_caps_raw_fd(file.as_raw_fd())?;
+        let mut caps = FileCaps::default();
+        caps.load_state();
+        let requested = caps.permitted.union(caps.inheritable);
+
+        get_caps_process()?;
+        let permitted = CapabilitySet::from_state(Type::PERMITTED);
+
+        Ok(CapDiff {
+            missing: requested.difference(permitted),
+            extra: permitted.difference(requested),
+        })
+    })
+}
+
+/// Declaratively builds the file capabilities to write to a file, like
+/// `setcap` does.
+///
+/// Adding a capability to `Type::EFFECTIVE` sets the file's effective bit,
+/// which raises all of its permitted and inheritable capabilities on exec.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FileCapsBuilder {
+    caps: FileCaps,
+}
+
+impl FileCapsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `capability` to the `which` vectors. Vectors other than the
+    /// effective, permitted and inheritable ones are ignored.
+    pub fn add(mut self, capability: Capability, which: Type) -> Self {
+        if which.contains(Type::EFFECTIVE) {
+            self.caps.effective.insert(capability);
+        }
+        if which.contains(Type::PERMITTED) {
+            self.caps.permitted.insert(capability);
+        }
+        if which.contains(Type::INHERITABLE) {
+            self.caps.inheritable.insert(capability);
+        }
+        self
+    }
+
+    /// Writes the capabilities to the extended attributes of `file`,
+    /// replacing any it already had.
+    ///
+    /// libcap-ng only writes file capabilities that include an effective
+    /// one, and removes them from the file otherwise. So if permitted or
+    /// inheritable capabilities were added but no effective one,
+    /// `Error::ApplyCapsFd` is returned and the file is left untouched,
+    /// rather than stripping its capabilities. An empty builder does remove
+    /// them, like `remove_file_caps` does.
+    ///
+    /// The libcap-ng state is saved before and restored afterwards, so this
+    /// doesn't disturb process capabilities being configured meanwhile.
+    pub fn write_to(&self, file: &File) -> Result<()> {
+        if self.caps.effective.is_empty()
+            && !(self.caps.permitted.is_empty() && self.caps.inheritable.is_empty())
+        {
+            return Err(Error::ApplyCapsFd(file.as_raw_fd()));
+        }
+
+        with_saved_state(|| {
+            clear(Set::CAPS);
+            let mut updates = Vec::new();
+            for which in Type::EFFECTIVE | Type::PERMITTED | Type::INHERITABLE {
+                updates.extend(self.caps.get(which).iter().map(|capability| CUpdate {
+                    action: Action::ADD,
+                    cap_type: which,
+                    capability,
+                }));
+            }
+            update(updates)?;
+
+            apply_caps_raw_fd(file.as_raw_fd())
+        })
+    }
+}
+
+/// Removes the file capabilities of `file`, like `setcap -r` does. A file
+/// without any is left as is.
+///
+/// The libcap-ng state is saved before and restored afterwards, so this
+/// doesn't disturb process capabilities being configured meanwhile.
+pub fn remove_file_caps(file: &File) -> Result<()> {
+    // libcap-ng removes the extended attribute when applying a state
+    // without capabilities, which fails if there's none to begin with.
+    let fd = file.as_raw_fd();
+    if !has_caps_xattr(fd).map_err(|_| Error::ApplyCapsFd(fd))? {
+        return Ok(());
+    }
+
+    with_saved_state(|| {
+        clear(Set::CAPS);
+        apply_caps_raw_fd(fd)
+    })
+}
+
+/// Like `remove_file_caps`, but for the file at `path`, following symbolic
+/// links.
+pub fn remove_file_caps_path<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|_| Error::OpenFile(path.to_path_buf()))?;
+
+    remove_file_caps(&file)
+}
+
+/// Walks the tree under `root`, calling `cb` for every regular file that
+/// carries file capabilities, like `getcap -r` does.
+///
+/// Symbolic links aren't followed, and files or directories that can't be
+/// read are skipped. Only failing to read `root` itself is an error. A single
+/// `FileCaps` is reused for every file, and the libcap-ng state is saved once
+/// for the whole walk and restored before returning.
+pub fn scan_caps<P: AsRef<Path>>(root: P, mut cb: impl FnMut(&Path, &FileCaps)) -> Result<()> {
+    let root = root.as_ref();
+    let metadata =
+        fs::symlink_metadata(root).map_err(|_| Error::ReadDirectory(root.to_path_buf()))?;
+
+    with_saved_state(|| {
+        let mut caps = FileCaps::default();
+        let mut check_file = |path: &Path| {
+            if let Ok(file) = open_nofollow(path) {
+                if get_caps_raw_fd(file.as_raw_fd()).is_ok() {
+                    caps.load_state();
+                    cb(path, &caps);
+                }
+            }
+        };
+
+        if !metadata.is_dir() {
+            if metadata.is_file() {
+                check_file(root);
+            }
+            return Ok(());
+        }
+
+        let mut dirs = vec![root.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) if dir == root => return Err(Error::ReadDirectory(dir)),
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                match entry.file_type() {
+                    Ok(t) if t.is_dir() => dirs.push(entry.path()),
+                    Ok(t) if t.is_file() => check_file(&entry.path()),
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(())
+    })
+}
+
+fn open_nofollow(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{apply_caps_fd, clear, update, Action, CUpdate, Set};
+    use std::path::PathBuf;
+
+    fn set_file_caps(path: &Path, caps: &[u32]) -> bool {
+        clear(Set::CAPS);
+        for &capability in caps {
+            update(vec![CUpdate {
+                action: Action::ADD,
+                cap_type: Type::PERMITTED | Type::EFFECTIVE,
+                capability,
+            }])
+            .unwrap();
+        }
+        apply_caps_fd(File::open(path).unwrap()).is_ok()
+    }
+
+    #[test]
+    fn scan_caps_tests() {
+        let root = std::env::temp_dir().join(format!("capng-{}-scan", std::process::id()));
+        fs::create_dir_all(root.join("sub")).unwrap();
+        for name in &["plain", "capped", "sub/capped"] {
+            File::create(root.join(name)).unwrap();
+        }
+
+        if set_file_caps(&root.join("capped"), &[0])
+            && set_file_caps(&root.join("sub/capped"), &[5, 10])
+        {
+            let mut found: Vec<(PathBuf, FileCaps)> = Vec::new();
+            scan_caps(&root, |path, caps| found.push((path.to_path_buf(), *caps))).unwrap();
+            found.sort_by(|a, b| a.0.cmp(&b.0));
+
+            assert_eq!(found.len(), 2);
+            assert_eq!(found[0].0, root.join("capped"));
+            assert_eq!(found[0].1.permitted.iter().collect::<Vec<_>>(), vec![0]);
+            assert_eq!(found[0].1.effective, found[0].1.permitted);
+            assert!(found[0].1.inheritable.is_empty());
+            assert_eq!(found[1].0, root.join("sub/capped"));
+            assert_eq!(found[1].1.permitted.iter().collect::<Vec<_>>(), vec![5, 10]);
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+        assert!(scan_caps(&root, |_, _| ()).is_err());
+    }
+
+    #[test]
+    fn file_caps_builder_without_effective_tests() {
+        let (path, file) = crate::tests::temp_file("builder-noeff");
+        let written = FileCapsBuilder::new()
+            .add(5, Type::PERMITTED | Type::EFFECTIVE)
+            .write_to(&file);
+
+        match FileCapsBuilder::new()
+            .add(10, Type::PERMITTED)
+            .add(13, Type::INHERITABLE)
+            .write_to(&file)
+        {
+            Err(Error::ApplyCapsFd(fd)) => assert_eq!(fd, file.as_raw_fd()),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        if written.is_ok() {
+            let caps = read_file_caps(&file).unwrap();
+            assert_eq!(caps.permitted.iter().collect::<Vec<_>>(), vec![5]);
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_caps_builder_tests() {
+        let (path, file) = crate::tests::temp_file("builder");
+        let written = FileCapsBuilder::new()
+            .add(10, Type::PERMITTED | Type::EFFECTIVE)
+            .add(13, Type::INHERITABLE)
+            .write_to(&file);
+
+        clear(Set::CAPS);
+        update(vec![CUpdate {
+            action: Action::ADD,
+            cap_type: Type::EFFECTIVE,
+            capability: 0,
+        }])
+        .unwrap();
+
+        if written.is_ok() {
+            let mut found = Vec::new();
+            scan_caps(&path, |_, caps| found.push(*caps)).unwrap();
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].permitted.iter().collect::<Vec<_>>(), vec![10]);
+            assert_eq!(found[0].inheritable.iter().collect::<Vec<_>>(), vec![13]);
+        }
+        assert_eq!(crate::capabilities_in(Type::EFFECTIVE), vec![0]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_file_caps_tests() {
+        let (path, file) = crate::tests::temp_file("read");
+        assert!(read_file_caps(&file).is_err());
+
+        if FileCapsBuilder::new()
+            .add(5, Type::PERMITTED | Type::EFFECTIVE)
+            .write_to(&file)
+            .is_ok()
+        {
+            clear(Set::CAPS);
+            let caps = read_file_caps(&file).unwrap();
+            assert_eq!(caps.permitted.iter().collect::<Vec<_>>(), vec![5]);
+            assert_eq!(caps.effective, caps.permitted);
+            assert!(crate::capabilities_in(Type::PERMITTED).is_empty());
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn remove_file_caps_tests() {
+        let (path, file) = crate::tests::temp_file("remove");
+        remove_file_caps(&file).unwrap();
+
+        clear(Set::CAPS);
+        update(vec![CUpdate {
+            action: Action::ADD,
+            cap_type: Type::EFFECTIVE,
+            capability: 0,
+        }])
+        .unwrap();
+        if FileCapsBuilder::new()
+            .add(5, Type::PERMITTED | Type::EFFECTIVE)
+            .write_to(&file)
+            .is_ok()
+        {
+            remove_file_caps_path(&path).unwrap();
+            assert_eq!(
+                read_file_caps_status(&file).unwrap(),
+                FileCapsStatus::Absent
+            );
+        }
+        assert_eq!(crate::capabilities_in(Type::EFFECTIVE), vec![0]);
+
+        fs::remove_file(&path).unwrap();
+        match remove_file_caps_path(&path) {
+            Err(Error::OpenFile(p)) => assert_eq!(p, path),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn file_caps_effective_bit_tests() {
+        let (path, file) = crate::tests::temp_file("effective");
+        assert!(!file_caps_effective_bit(&file).unwrap());
+
+        if FileCapsBuilder::new()
+            .add(5, Type::PERMITTED | Type::EFFECTIVE)
+            .write_to(&file)
+            .is_ok()
+        {
+            assert!(file_caps_effective_bit(&file).unwrap());
+
+            // libcap-ng can't write capabilities without the effective bit,
+            // so write version 2 ones, with only CAP_KILL permitted, as is.
+            let mut data = [0u8; 20];
+            data[..4].copy_from_slice(&0x0200_0000u32.to_le_bytes());
+            data[4..8].copy_from_slice(&(1u32 << 5).to_le_bytes());
+            // Safe because the name is nul-terminated and data outlives the
+            // call.
+            let ret = unsafe {
+                libc::fsetxattr(
+                    file.as_raw_fd(),
+                    CAPS_XATTR.as_ptr() as *const libc::c_char,
+                    data.as_ptr() as *const libc::c_void,
+                    data.len(),
+                    0,
+                )
+            };
+            assert_eq!(ret, 0);
+            assert!(!file_caps_effective_bit(&file).unwrap());
+            assert_eq!(read_file_caps(&file).unwrap().permitted.len(), 1);
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_file_caps_status_tests() {
+        let (path, file) = crate::tests::temp_file("status");
+        assert_eq!(
+            read_file_caps_status(&file).unwrap(),
+            FileCapsStatus::Absent
+        );
+
+        if FileCapsBuilder::new()
+            .add(0, Type::PERMITTED | Type::EFFECTIVE)
+            .write_to(&file)
+            .is_ok()
+        {
+            match read_file_caps_status(&file).unwrap() {
+                FileCapsStatus::Present(caps) => {
+                    assert_eq!(caps.permitted.iter().collect::<Vec<_>>(), vec![0])
+                }
+                FileCapsStatus::Absent => panic!("file capabilities not found"),
+            }
+        }
+        fs::remove_file(&path).unwrap();
+
+        // Pipes can't have extended attributes at all.
+        let mut fds = [0; 2];
+        // Safe because fds has room for both ends, and we take ownership of
+        // them right away.
+        let pipe: File = unsafe {
+            assert_eq!(libc::pipe(fds.as_mut_ptr()), 0);
+            libc::close(fds[1]);
+            std::os::unix::io::FromRawFd::from_raw_fd(fds[0])
+        };
+        assert!(read_file_caps_status(&pipe).is_err());
+    }
+
+    #[test]
+    fn diff_file_vs_process_tests() {
+        let (path, file) = crate::tests::temp_file("diff");
+        // Not permitted in the test environment.
+        let sys_resource = crate::cap!(SYS_RESOURCE);
+
+        if !crate::can_raise(sys_resource)
+            && FileCapsBuilder::new()
+                .add(0, Type::PERMITTED | Type::EFFECTIVE)
+                .add(sys_resource, Type::INHERITABLE)
+                .write_to(&file)
+                .is_ok()
+        {
+            clear(Set::CAPS);
+            let diff = diff_file_vs_process(&file).unwrap();
+            assert_eq!(diff.missing.iter().collect::<Vec<_>>(), vec![sys_resource]);
+            assert!(!diff.extra.contains(0));
+            assert!(diff.extra.contains(1));
+            assert!(diff
+                .to_string()
+                .starts_with("missing: sys_resource; extra: dac_override, "));
+            assert!(crate::capabilities_in(Type::PERMITTED).is_empty());
+        }
+
+        assert_eq!(CapDiff::default().to_string(), "missing: none; extra: none");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn lint_file_caps_tests() {
+        let (path, file) = crate::tests::temp_file("lint");
+
+        if FileCapsBuilder::new()
+            .add(10, Type::PERMITTED | Type::EFFECTIVE)
+            .add(12, Type::PERMITTED | Type::EFFECTIVE)
+            .add(13, Type::INHERITABLE)
+            .write_to(&file)
+            .is_ok()
+        {
+            let allowed: CapabilitySet = [10].iter().collect();
+            assert_eq!(lint_file_caps(&file, &allowed).unwrap(), vec![12, 13]);
+            let allowed: CapabilitySet = [10, 12, 13, 21].iter().collect();
+            assert!(lint_file_caps(&file, &allowed).unwrap().is_empty());
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_caps_iter_tests() {
+        let (path, file) = crate::tests::temp_file("iter");
+        assert!(file_caps_iter(&file, Type::PERMITTED).is_err());
+
+        if FileCapsBuilder::new()
+            .add(12, Type::PERMITTED | Type::EFFECTIVE)
+            .add(1, Type::PERMITTED | Type::EFFECTIVE)
+            .add(5, Type::INHERITABLE)
+            .write_to(&file)
+            .is_ok()
+        {
+            clear(Set::CAPS);
+            let permitted: Vec<_> = file_caps_iter(&file, Type::PERMITTED).unwrap().collect();
+            assert_eq!(permitted, vec![1, 12]);
+            let all: Vec<_> = file_caps_iter(&file, Type::PERMITTED | Type::INHERITABLE)
+                .unwrap()
+                .collect();
+            assert_eq!(all, vec![1, 5, 12]);
+            assert!(crate::capabilities_in(Type::PERMITTED).is_empty());
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+}
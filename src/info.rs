@@ -0,0 +1,101 @@
+// Copyright (C) 2020 Red Hat, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+use crate::Capability;
+
+/// A broad classification of capabilities by what they're concerned with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Category {
+    /// Sockets, interfaces and network configuration.
+    Network,
+    /// File ownership, permissions and attributes.
+    Filesystem,
+    /// Process credentials, signals, scheduling and resources.
+    ProcessControl,
+    /// System-wide administration, like modules, clocks and auditing.
+    SystemAdmin,
+    /// Anything else.
+    Other,
+}
+
+/// Static, human-readable metadata about a capability.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CapabilityInfo {
+    /// The name as used by libcap-ng, e.g. `"net_admin"`.
+    pub name: &'static str,
+    /// What the capability is concerned with.
+    pub category: Category,
+    /// A short sentence describing what the capability allows.
+    pub description: &'static str,
+    /// The kernel version the capability was introduced in, e.g. `"2.6.24"`.
+    pub since_kernel: &'static str,
+}
+
+// The metadata table and the `consts` module, generated by the build script
+// from `capabilities.txt`.
+include!(concat!(env!("OUT_DIR"), "/capabilities.rs"));
+
+/// Returns static metadata about `cap`, or `None` if it's unknown to the
+/// crate.
+///
+/// Unlike `capability_to_name`, this doesn't depend on the libcap-ng version
+/// the crate runs against.
+pub fn capability_info(cap: Capability) -> Option<CapabilityInfo> {
+    CAPABILITIES.get(cap as usize).copied()
+}
+
+/// Returns the category of `cap`, or `Category::Other` if it's unknown to the
+/// crate.
+pub fn capability_category(cap: Capability) -> Category {
+    capability_info(cap).map_or(Category::Other, |info| info.category)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{capability_to_name, known_capabilities};
+
+    #[test]
+    fn capability_info_tests() {
+        for cap in known_capabilities() {
+            let info = capability_info(cap).unwrap();
+            assert_eq!(info.name, capability_to_name(cap).unwrap());
+            assert!(info.description.ends_with('.'));
+        }
+
+        for (index, &id) in IDS.iter().enumerate() {
+            assert_eq!(index, id as usize);
+        }
+        assert_eq!(consts::NET_ADMIN, 12);
+        assert_eq!(capability_info(31).unwrap().since_kernel, "2.6.24");
+        assert_eq!(capability_info(CAPABILITIES.len() as Capability), None);
+    }
+
+    #[test]
+    fn consts_tests() {
+        let _hooks = crate::tests::lock_hooks();
+        for cap in known_capabilities() {
+            let name = capability_info(cap).unwrap().name;
+            assert_eq!(crate::name_to_capability(name).unwrap(), IDS[cap as usize]);
+        }
+        assert_eq!(
+            crate::cap::NET_ADMIN,
+            crate::name_to_capability("NET_ADMIN").unwrap()
+        );
+        assert_eq!(crate::cap::CHOWN, 0);
+    }
+
+    #[test]
+    fn capability_category_tests() {
+        assert!(known_capabilities().all(|cap| capability_info(cap).is_some()));
+        assert_eq!(capability_category(consts::NET_RAW), Category::Network);
+        assert_eq!(capability_category(consts::CHOWN), Category::Filesystem);
+        assert_eq!(capability_category(consts::KILL), Category::ProcessControl);
+        assert_eq!(
+            capability_category(consts::SYS_ADMIN),
+            Category::SystemAdmin
+        );
+        assert_eq!(capability_category(1000), Category::Other);
+    }
+}
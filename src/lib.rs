@@ -2,44 +2,232 @@
 //
 // SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
 
+use std::cell::Cell;
 use std::convert::TryFrom;
+use std::env;
 use std::error;
-use std::ffi::{CStr, CString};
+use std::ffi::CStr;
 use std::fmt;
 use std::fs::File;
-use std::os::raw::c_char;
-use std::os::unix::io::AsRawFd;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::os::raw::{c_char, c_int};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
 
 #[macro_use]
 extern crate bitflags;
+#[macro_use]
+extern crate log;
+
+/// Enters a trace span for the rest of the enclosing block when the
+/// `tracing` feature is enabled, and expands to nothing otherwise. Takes the
+/// same arguments as `tracing::trace_span!`.
+macro_rules! ffi_span {
+    ($($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!($($args)*).entered();
+    };
+}
 
+pub mod abi;
 mod bindings;
+mod capset;
+mod file;
+mod info;
+pub mod names;
+mod operations;
+mod policy;
+mod preflight;
+mod process;
+#[cfg(feature = "pure-rust")]
+pub mod pure;
+mod score;
+mod state;
+mod support;
+
+/// Raw, unsafe FFI declarations for libcap-ng.
+///
+/// This is an escape hatch for calling libcap-ng functions this crate doesn't
+/// wrap yet. Nothing in here is covered by the crate's safety guarantees, and
+/// its contents may change in any release without notice.
+#[cfg(feature = "unstable-ffi")]
+pub mod sys {
+    pub use crate::bindings::*;
+}
+
+pub use capset::CapabilitySet;
+pub use file::{
+    diff_file_vs_process, file_caps_effective_bit, file_caps_iter, lint_file_caps, read_file_caps,
+    read_file_caps_status, remove_file_caps, remove_file_caps_path, scan_caps, CapDiff, FileCaps,
+    FileCapsBuilder, FileCapsStatus,
+};
+pub use info::consts as cap;
+pub use info::{capability_category, capability_info, CapabilityInfo, Category};
+pub use names::{
+    capability_to_name, clear_name_resolver, lookup_name_fast, name_to_capability,
+    set_name_resolver, ToCapability,
+};
+pub use operations::{caps_for_operations, explain_denied, Operation};
+pub use policy::{verify_policy, CapPolicy, CapRequirements, PolicyViolation};
+pub use preflight::{preflight, PreflightReport};
+pub use process::{
+    apply_to_cgroup, capability_masks, capability_matrix, caps_to_json, caps_to_proc_format,
+    read_caps_pidfd, read_cgroup_caps, CapMasks, ProcessCaps,
+};
+pub use score::{clear_privilege_weights, privilege_score, set_privilege_weights};
+pub use state::{
+    assert_caps_eq, caps_changed_since, disable_caps_cache, enable_caps_cache, export_state_to_env,
+    import_state_from_env, invalidate_caps_cache, namespace_init_caps, reconcile, CapState,
+};
+pub use support::{get_rootid, kernel_last_cap, set_rootid, supports_ambient, UNSET_ROOTID};
+
+const PROC_SELF: &str = "/proc/self";
+
+/// The capabilities checked by `running_privileged`.
+const PRIVILEGED_CAPS: [Capability; 3] = [cap!(SETPCAP), cap!(SETUID), cap!(SETGID)];
+
+/// Whether calls changing the process capabilities only log what they
+/// would do, see `set_dry_run`.
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    // Like the libcap-ng state it tracks, this is per thread.
+    static GENERATION: Cell<u64> = const { Cell::new(0) };
+    // Whether the libcap-ng state was loaded or initialized, see
+    // `CapsNotLoaded`.
+    static LOADED: Cell<bool> = const { Cell::new(false) };
+    // The capabilities added to the bounding set of the libcap-ng state
+    // since it was last loaded or reset, see `InvalidUpdate`.
+    static BOUNDING_ADDS: Cell<CapabilitySet> = const { Cell::new(CapabilitySet::empty()) };
+}
+
+/// Expands to the ID of the capability named like `CAP_*` without the
+/// prefix, e.g. `cap!(NET_ADMIN)`, failing to compile for unknown names.
+///
+/// This spares `name_to_capability` lookups, and their failures at runtime,
+/// for capabilities known when writing the code. It's the same as naming the
+/// constant in the `cap` module, e.g. `cap::NET_ADMIN`.
+#[macro_export]
+macro_rules! cap {
+    ($name:ident) => {
+        $crate::cap::$name
+    };
+}
 
 pub type Pid = i32;
+/// A user ID, where -1 leaves the current one unchanged.
+pub type Uid = i32;
+/// A group ID, where -1 leaves the current one unchanged.
+pub type Gid = i32;
+/// A capability ID, as understood by libcap-ng and the kernel.
+///
+/// Being a plain integer, it is `Copy`, `Eq`, `Hash` and `Ord`, so it can be
+/// used as a key in `HashSet`/`BTreeSet` and friends, where it orders by ID.
 pub type Capability = u32;
 
 #[derive(Debug)]
 pub enum Error {
+    /// An ambient capability isn't also in the permitted and inheritable sets.
+    AmbientPreconditionUnmet(Capability),
     /// Failed to sync capabilities with the kernel.
     ApplyCapabilities,
     /// Failed to write capabilities to the extended attributes of File.
     ApplyCapsFile(File),
+    /// Failed to write capabilities to the extended attributes of the file
+    /// descriptor.
+    ApplyCapsFd(RawFd),
+    /// The process capabilities differ from the expected ones.
+    CapsMismatch {
+        expected: CapState,
+        actual: CapState,
+    },
+    /// The capabilities were queried before being loaded with
+    /// `get_caps_process`, `get_caps_file`, `fill` or `clear`.
+    CapsNotLoaded,
     /// Failed to change the target process ID.
     ChangeTargetId,
     /// Failed to convert capability name to a C-compatible representation.
     ConvertCapabilityName,
+    /// `forbid_forever` failed, with `bounding` holding the error dropping
+    /// the capabilities from the bounding set, if that failed, and
+    /// `no_new_privs` telling whether the no_new_privs flag was set anyway.
+    /// Without it, exec can still grant the capabilities again.
+    ForbidForever {
+        bounding: Option<Box<Error>>,
+        no_new_privs: bool,
+    },
+    /// Failed to probe the capability ABI version of the kernel.
+    GetCapabilityAbiVersion,
     /// Failed to get the ID for a capability's name.
     GetCapabilityId(String),
     /// Failed to read the capabilities from the extended attributes of File.
     GetCapsFile(File),
+    /// Failed to read the capabilities from the extended attributes of the
+    /// file descriptor.
+    GetCapsFd(RawFd),
+    /// Failed to read the highest capability supported by the running
+    /// kernel.
+    GetKernelLastCap,
+    /// Failed to read the no_new_privs flag.
+    GetNoNewPrivs,
+    /// Failed to identify a live process from a pidfd.
+    GetPidfdTarget(RawFd),
     /// Failed to get process capabilities.
     GetProcessCapabilities,
+    /// Failed to get process capabilities due to insufficient permissions.
+    GetProcessCapabilitiesDenied,
+    /// Failed to read the securebits.
+    GetSecurebits,
+    /// The capabilities checked by `require_full` aren't all held, as told
+    /// by the `HaveCapsResult`.
+    InsufficientCapabilities(HaveCapsResult),
+    /// Invalid value for Action enum.
+    InvalidAction(u32),
     /// Invalid value for HaveCapsResult enum.
     InvalidHaveCapsResult(i32),
+    /// Invalid value for Print enum.
+    InvalidPrint(u32),
+    /// The bytes don't hold a serialized `CapState`, or one written in an
+    /// unknown format version.
+    InvalidStateBytes,
+    /// A capability was added to the bounding set of the libcap-ng state,
+    /// but it's no longer in the bounding set of the calling thread, where
+    /// it can't be brought back.
+    InvalidUpdate(Capability),
     /// Failed to lock capabilities.
     LockCapabilities,
+    /// A required capability is missing from the effective set.
+    MissingCapability(Capability),
+    /// The process runs the given number of threads, so changing the
+    /// capabilities of the calling thread would leave the others untouched.
+    MultipleThreads(usize),
     /// Failed to find the name corresponding to Capability.
     NameToCapability(Capability),
+    /// Failed to open the file at the given path.
+    OpenFile(PathBuf),
+    /// Failed to get process capabilities because procfs isn't mounted.
+    ProcfsNotMounted,
+    /// Failed to read the environment variable with the given name.
+    ReadEnvironmentVariable(String),
+    /// Failed to read the directory at the given path.
+    ReadDirectory(PathBuf),
+    /// Failed to save the capabilities state.
+    SaveState,
+    /// Saving and restoring the libcap-ng state didn't preserve it.
+    SelfCheck,
+    /// Failed to set the no_new_privs flag.
+    SetNoNewPrivs,
+    /// Failed to set the root ID used for file capabilities.
+    SetRootId(i32),
+    /// The linked libcap-ng is too old to support the named feature.
+    UnsupportedByLibrary(&'static str),
+    /// The selected parts of the libcap-ng state can't be applied to another
+    /// process.
+    UnsupportedSet(Set),
     /// Failed to update the capability's status.
     UpdateCapability(Capability),
 }
@@ -50,17 +238,57 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use Error::*;
         match self {
+            AmbientPreconditionUnmet(cap) => write!(
+                f,
+                "ambient capability {} must also be permitted and inheritable",
+                DisplayCapability(*cap)
+            ),
             ApplyCapabilities => write!(f, "failed to sync capabilities with the kernel"),
             ApplyCapsFile(file) => write!(
                 f,
                 "failed to write capabilities to the extended attributes of {:?}",
                 file
             ),
+            ApplyCapsFd(fd) => write!(
+                f,
+                "failed to write capabilities to the extended attributes of fd {}",
+                fd
+            ),
+            CapsMismatch { expected, actual } => write!(
+                f,
+                "capabilities {:?} differ from the expected {:?}",
+                actual, expected
+            ),
+            CapsNotLoaded => write!(
+                f,
+                "capabilities not loaded, call get_caps_process, get_caps_file, fill or clear first"
+            ),
             ChangeTargetId => write!(f, "failed to change target process id"),
             ConvertCapabilityName => write!(
                 f,
                 "failed to convert capability name to a C-compatible representation"
             ),
+            ForbidForever {
+                bounding,
+                no_new_privs,
+            } => {
+                write!(f, "failed to forbid capabilities for good: ")?;
+                match bounding {
+                    Some(e) => write!(f, "{}", e)?,
+                    None => write!(f, "dropped them from the bounding set")?,
+                }
+                if *no_new_privs {
+                    write!(f, ", set the no_new_privs flag")
+                } else {
+                    write!(f, ", failed to set the no_new_privs flag")
+                }
+            }
+            GetCapabilityAbiVersion => {
+                write!(
+                    f,
+                    "failed to probe the capability ABI version of the kernel"
+                )
+            }
             GetCapabilityId(cap_name) => write!(
                 f,
                 "failed to get the ID for the capability with name {}",
@@ -71,14 +299,74 @@ impl fmt::Display for Error {
                 "failed to read the capabilities from the extended attributes of {:?}",
                 file
             ),
+            GetCapsFd(fd) => write!(
+                f,
+                "failed to read the capabilities from the extended attributes of fd {}",
+                fd
+            ),
+            GetKernelLastCap => write!(
+                f,
+                "failed to read the highest capability supported by the kernel"
+            ),
+            GetNoNewPrivs => write!(f, "failed to read the no_new_privs flag"),
+            GetPidfdTarget(fd) => write!(f, "failed to identify a live process from pidfd {}", fd),
             GetProcessCapabilities => write!(f, "failed to get process capabilities"),
+            GetProcessCapabilitiesDenied => {
+                write!(f, "failed to get process capabilities: permission denied")
+            }
+            GetSecurebits => write!(f, "failed to read the securebits"),
+            InsufficientCapabilities(result) => write!(
+                f,
+                "expected full capabilities, but the result was {:?}",
+                result
+            ),
+            InvalidAction(value) => write!(f, "invalid value {} for Action", value),
             InvalidHaveCapsResult(value) => write!(f, "invalid value {} for HaveCapsResult", value),
+            InvalidPrint(value) => write!(f, "invalid value {} for Print", value),
+            InvalidStateBytes => write!(f, "invalid serialized capability state"),
+            InvalidUpdate(cap) => write!(
+                f,
+                "capability {} can't be added back to the bounding set, only dropped",
+                DisplayCapability(*cap)
+            ),
             LockCapabilities => write!(f, "failed to lock capabilities"),
-            NameToCapability(cap) => write!(f, "failed to find the name for capability {}", cap),
+            MissingCapability(cap) => {
+                write!(f, "missing required capability {}", DisplayCapability(*cap))
+            }
+            MultipleThreads(count) => write!(
+                f,
+                "the process runs {} threads, but capabilities only change for the calling one",
+                count
+            ),
+            NameToCapability(cap) => write!(
+                f,
+                "failed to find the name for capability {}",
+                DisplayCapability(*cap)
+            ),
+            OpenFile(path) => write!(f, "failed to open the file {:?}", path),
+            ProcfsNotMounted => write!(
+                f,
+                "failed to get process capabilities: /proc is not mounted"
+            ),
+            ReadEnvironmentVariable(var) => {
+                write!(f, "failed to read the environment variable {}", var)
+            }
+            ReadDirectory(path) => write!(f, "failed to read the directory {:?}", path),
+            SaveState => write!(f, "failed to save the capabilities state"),
+            SelfCheck => write!(
+                f,
+                "saving and restoring the capabilities state didn't preserve it"
+            ),
+            SetNoNewPrivs => write!(f, "failed to set the no_new_privs flag"),
+            SetRootId(rootid) => write!(f, "failed to set the root id to {}", rootid),
+            UnsupportedByLibrary(feature) => {
+                write!(f, "the linked libcap-ng doesn't support {}", feature)
+            }
+            UnsupportedSet(set) => write!(f, "{:?} can't be applied to another process", set),
             UpdateCapability(cap) => write!(
                 f,
-                "failed to update the status of the capability with name {}",
-                cap
+                "failed to update the status of capability {}",
+                DisplayCapability(*cap)
             ),
         }
     }
@@ -86,12 +374,26 @@ impl fmt::Display for Error {
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
+/// Displays a capability by name and ID, e.g. `CAP_NET_ADMIN (12)`, falling
+/// back to just the ID when libcap-ng doesn't know its name.
+pub(crate) struct DisplayCapability(pub(crate) Capability);
+
+impl fmt::Display for DisplayCapability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match capability_to_name(self.0) {
+            Ok(name) => write!(f, "CAP_{} ({})", name.to_uppercase(), self.0),
+            Err(_) => write!(f, "{}", self.0),
+        }
+    }
+}
+
 bitflags! {
     pub struct Type: u32 {
         const EFFECTIVE = 1;
         const PERMITTED = 2;
         const INHERITABLE = 4;
         const BOUNDING_SET = 8;
+        const AMBIENT = 16;
     }
 }
 
@@ -100,6 +402,8 @@ bitflags! {
         const CAPS = 16;
         const BOUNDS = 32;
         const BOTH = Self::CAPS.bits() | Self::BOUNDS.bits();
+        const AMBIENT = 64;
+        const ALL = Self::BOTH.bits() | Self::AMBIENT.bits();
     }
 }
 
@@ -108,22 +412,119 @@ bitflags! {
         const DROP_SUPP_GRP = 1;
         const CLEAR_BOUNDING = 2;
         const INIT_SUPP_GRP = 4;
+        const CLEAR_AMBIENT = 8;
+    }
+}
+
+bitflags! {
+    /// The securebits controlling how root and ID changes affect
+    /// capabilities, see `lock_bits`.
+    pub struct Securebits: u32 {
+        const NOROOT = 1 << 0;
+        const NOROOT_LOCKED = 1 << 1;
+        const NO_SETUID_FIXUP = 1 << 2;
+        const NO_SETUID_FIXUP_LOCKED = 1 << 3;
+        const KEEP_CAPS = 1 << 4;
+        const KEEP_CAPS_LOCKED = 1 << 5;
+        const NO_CAP_AMBIENT_RAISE = 1 << 6;
+        const NO_CAP_AMBIENT_RAISE_LOCKED = 1 << 7;
+    }
+}
+
+impl IntoIterator for Type {
+    type Item = Type;
+    type IntoIter = TypeIter;
+
+    /// Iterates over the individual vectors in the `Type`, from the lowest
+    /// bit to the highest.
+    fn into_iter(self) -> TypeIter {
+        TypeIter { bits: self.bits() }
+    }
+}
+
+/// Iterator over the single-vector `Type`s making up a `Type`.
+#[derive(Clone, Debug)]
+pub struct TypeIter {
+    bits: u32,
+}
+
+impl Iterator for TypeIter {
+    type Item = Type;
+
+    fn next(&mut self) -> Option<Type> {
+        if self.bits == 0 {
+            return None;
+        }
+        let bit = self.bits & self.bits.wrapping_neg();
+        self.bits &= !bit;
+        Some(Type::from_bits_truncate(bit))
+    }
+}
+
+// bitflags already provides const constructors and set operations (`empty`,
+// `from_bits_truncate`, `union`, ...). These by-value accessors round out
+// what's needed to build capability tables in const contexts.
+
+impl Type {
+    pub const fn bits_of(self) -> u32 {
+        self.bits()
     }
 }
 
-#[derive(Copy, Clone)]
+impl Set {
+    pub const fn bits_of(self) -> u32 {
+        self.bits()
+    }
+}
+
+impl Flags {
+    pub const fn bits_of(self) -> u32 {
+        self.bits()
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Action {
     DROP = 0,
     ADD = 1,
 }
 
-#[derive(Copy, Clone)]
+impl TryFrom<u32> for Action {
+    type Error = crate::Error;
+
+    fn try_from(n: u32) -> Result<Self> {
+        match n {
+            0 => Ok(Action::DROP),
+            1 => Ok(Action::ADD),
+            _ => Err(Error::InvalidAction(n)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Print {
     STDOUT = 0,
     BUFFER = 1,
 }
 
-#[derive(PartialEq, Debug)]
+impl TryFrom<u32> for Print {
+    type Error = crate::Error;
+
+    fn try_from(n: u32) -> Result<Self> {
+        match n {
+            0 => Ok(Print::STDOUT),
+            1 => Ok(Print::BUFFER),
+            _ => Err(Error::InvalidPrint(n)),
+        }
+    }
+}
+
+/// How many capabilities a check found, from none to all of them.
+///
+/// The variants are ordered, `FAIL` being the lowest and `FULL` the highest,
+/// see `at_least`.
+#[must_use = "a capability check is pointless if its result is ignored"]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum HaveCapsResult {
     FAIL = -1,
     NONE = 0,
@@ -131,6 +532,26 @@ pub enum HaveCapsResult {
     FULL = 2,
 }
 
+impl HaveCapsResult {
+    pub fn is_full(self) -> bool {
+        self == HaveCapsResult::FULL
+    }
+
+    pub fn is_partial(self) -> bool {
+        self == HaveCapsResult::PARTIAL
+    }
+
+    pub fn is_none(self) -> bool {
+        self == HaveCapsResult::NONE
+    }
+
+    /// Returns whether the check found at least as many capabilities as
+    /// `other`, e.g. `PARTIAL` or `FULL` for `at_least(PARTIAL)`.
+    pub fn at_least(self, other: HaveCapsResult) -> bool {
+        self as i32 >= other as i32
+    }
+}
+
 impl TryFrom<i32> for HaveCapsResult {
     type Error = crate::Error;
 
@@ -145,80 +566,457 @@ impl TryFrom<i32> for HaveCapsResult {
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub struct CUpdate {
     pub action: Action,
     pub cap_type: Type,
     pub capability: Capability,
 }
 
+impl fmt::Display for CUpdate {
+    /// Displays the update like `+EFFECTIVE|PERMITTED CAP_CHOWN`, with `-`
+    /// for drops.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = match self.action {
+            Action::ADD => '+',
+            Action::DROP => '-',
+        };
+        write!(f, "{}", sign)?;
+        for (i, t) in self.cap_type.into_iter().enumerate() {
+            write!(f, "{}{:?}", if i == 0 { "" } else { "|" }, t)?;
+        }
+        match capability_to_name(self.capability) {
+            Ok(name) => write!(f, " CAP_{}", name.to_uppercase()),
+            Err(_) => write!(f, " {}", self.capability),
+        }
+    }
+}
+
 pub struct CapngState {
-    opaque: *mut ::std::os::raw::c_void,
+    // A Cell, so state_matches can swap in a fresh copy after restoring it.
+    opaque: Cell<*mut ::std::os::raw::c_void>,
+    loaded: bool,
+    bounding_adds: CapabilitySet,
 }
 
 unsafe impl Send for CapngState {}
 
+/// Targets another process until dropped, see `with_pid`.
+#[must_use = "the target process is reset to self as soon as the scope is dropped"]
+pub struct PidScope {
+    // Not Send, as libcap-ng keeps the target pid per thread.
+    _marker: PhantomData<*const ()>,
+}
+
+impl Drop for PidScope {
+    fn drop(&mut self) {
+        setpid(0);
+    }
+}
+
 pub fn clear(set: Set) {
+    ffi_span!("clear", ?set);
+    bump_generation();
+    set_loaded();
+    if set.contains(Set::BOUNDS) {
+        reset_bounding_adds();
+    }
     // Safe because it doesn't modify any local memory.
     unsafe {
-        bindings::capng_clear(set.bits() as u32);
+        bindings::capng_clear(set.bits());
     }
 }
 
 pub fn fill(set: Set) {
+    ffi_span!("fill", ?set);
+    bump_generation();
+    set_loaded();
+    if set.contains(Set::BOUNDS) {
+        reset_bounding_adds();
+    }
     // Safe because it doesn't modify any local memory.
     unsafe {
-        bindings::capng_fill(set.bits() as u32);
+        bindings::capng_fill(set.bits());
     }
 }
 
 pub fn setpid(pid: Pid) {
+    ffi_span!("setpid", pid);
+    bump_generation();
     // Safe because it doesn't modify any local memory.
     unsafe {
         bindings::capng_setpid(pid);
     }
 }
 
+/// Makes `pid` the target of `get_caps_process` and `apply` until the
+/// returned guard is dropped, after which the calling process is targeted
+/// again.
+///
+/// This avoids accidentally leaving libcap-ng pointed at another process
+/// after inspecting it.
+pub fn with_pid(pid: Pid) -> PidScope {
+    setpid(pid);
+    PidScope {
+        _marker: PhantomData,
+    }
+}
+
+/// Runs `f` with `pid` as the target of `get_caps_process` and `apply`, then
+/// targets the calling process again, even if `f` fails or panics.
+///
+/// This is the closure form of `with_pid`, e.g.
+/// `with_target_pid(pid, CapState::capture)`.
+pub fn with_target_pid<T>(pid: Pid, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let _scope = with_pid(pid);
+    f()
+}
+
 pub fn get_caps_process() -> Result<()> {
+    ffi_span!("get_caps_process");
+    bump_generation();
     // Safe because it doesn't modify any local memory.
     let ret = unsafe { bindings::capng_get_caps_process() };
 
     if ret == 0 {
-        Ok(())
+        set_loaded();
+        reset_bounding_adds();
+        return Ok(());
+    }
+
+    // libcap-ng reads part of the state from /proc/<pid>/status, so tell a
+    // missing procfs apart from the process being off limits to us.
+    let errno = io::Error::last_os_error().raw_os_error();
+    if !Path::new(PROC_SELF).exists() {
+        Err(Error::ProcfsNotMounted)
+    } else if errno == Some(libc::EPERM) || errno == Some(libc::EACCES) {
+        Err(Error::GetProcessCapabilitiesDenied)
     } else {
         Err(Error::GetProcessCapabilities)
     }
 }
 
 pub fn update(updates: Vec<CUpdate>) -> Result<()> {
+    ffi_span!("update", count = updates.len());
+    bump_generation();
+    if updates.iter().any(|u| u.cap_type.contains(Type::AMBIENT)) {
+        support::check_ambient("ambient")?;
+    }
+
     for u in updates {
+        ffi_span!(
+            "capng_update",
+            action = ?u.action,
+            cap_type = ?u.cap_type,
+            capability = u.capability
+        );
         // Safe because this doesn't modify any local memory.
-        let ret = unsafe {
-            bindings::capng_update(u.action as u32, u.cap_type.bits() as u32, u.capability)
-        };
+        let ret =
+            unsafe { bindings::capng_update(u.action as u32, u.cap_type.bits(), u.capability) };
         if ret < 0 {
             return Err(Error::UpdateCapability(u.capability));
         }
+        track_bounding_update(u.action, u.cap_type, u.capability);
     }
 
     Ok(())
 }
 
 pub fn updatev(action: Action, _type: Type, names: Vec<&str>) -> Result<()> {
+    ffi_span!("updatev", ?action, cap_type = ?_type);
+    bump_generation();
+    if _type.contains(Type::AMBIENT) {
+        support::check_ambient("ambient")?;
+    }
+
     for name in names {
         let cap = name_to_capability(name)?;
         // Safe because this doesn't modify any local memory.
-        let ret = unsafe { bindings::capng_update(action as u32, _type.bits() as u32, cap) };
+        let ret = unsafe { bindings::capng_update(action as u32, _type.bits(), cap) };
         if ret < 0 {
             return Err(Error::UpdateCapability(cap));
         }
+        track_bounding_update(action, _type, cap);
     }
 
     Ok(())
 }
 
+/// Adds `capability` to the `which` vector of the libcap-ng state if
+/// `present` is true, or drops it otherwise, returning whether it was there
+/// before, e.g. to undo the change later. `which` must name a single vector.
+///
+/// Like `update`, this only changes the state, and nothing is applied until
+/// `apply` is called.
+pub fn set_capability(which: Type, capability: Capability, present: bool) -> Result<bool> {
+    let previous = have_capability(which, capability);
+    update(vec![CUpdate {
+        action: if present { Action::ADD } else { Action::DROP },
+        cap_type: which,
+        capability,
+    }])?;
+
+    Ok(previous)
+}
+
+/// Replaces the capabilities in `set` with the ones listed in the `var`
+/// environment variable, adding them to the `types` vectors, and applies the
+/// result.
+///
+/// The variable holds a comma-separated list of capability names, in the
+/// format accepted by `parse_caps_text`, e.g. `KEEP_CAPS=CHOWN,NET_BIND_SERVICE`.
+pub fn apply_from_env(var: &str, types: Type, set: Set) -> Result<()> {
+    let value = env::var(var).map_err(|_| Error::ReadEnvironmentVariable(var.to_string()))?;
+    let caps = parse_caps_text(&value)?;
+
+    clear(set);
+    update(
+        caps.into_iter()
+            .map(|capability| CUpdate {
+                action: Action::ADD,
+                cap_type: types,
+                capability,
+            })
+            .collect(),
+    )?;
+
+    apply(set)
+}
+
+/// Applies the selected parts of the libcap-ng state to the target process.
+///
+/// If `set` includes `Set::AMBIENT`, e.g. through `Set::ALL`, and the
+/// libcap-ng in use can't handle the ambient set,
+/// `Error::UnsupportedByLibrary` is returned and nothing is applied.
+///
+/// The bounding set can only be reduced at runtime. If `set` includes
+/// `Set::BOUNDS` and a capability added to the bounding set with `update` or
+/// `updatev` is missing from the bounding set of the calling thread,
+/// `Error::InvalidUpdate` is returned and nothing is applied, rather than
+/// silently keeping it out.
 pub fn apply(set: Set) -> Result<()> {
+    ffi_span!("apply", ?set);
+    if set.contains(Set::AMBIENT) {
+        support::check_ambient("ambient")?;
+    }
+    if set.contains(Set::BOUNDS) {
+        check_bounding_adds()?;
+    }
+
+    if dry_run() {
+        info!(
+            "dry run: not applying {:?}: {}",
+            set,
+            print_caps_numeric(Print::BUFFER, set).unwrap_or_default()
+        );
+        return Ok(());
+    }
+
     // Safe because this doesn't modify any local memory.
-    let ret = unsafe { bindings::capng_apply(set.bits() as u32) };
+    let ret = unsafe { bindings::capng_apply(set.bits()) };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(Error::ApplyCapabilities)
+    }
+}
+
+/// Like `apply`, but tries to roll back on failure instead of leaving the
+/// selected parts half-applied, e.g. the capabilities applied but the
+/// ambient set rejected.
+///
+/// The capabilities of the process are saved beforehand, and on failure
+/// they're applied again, after which the libcap-ng state is put back as it
+/// was configured and the original error is returned. The rollback is best
+/// effort, as the kernel never gives back a capability dropped from the
+/// permitted or bounding set. Its own failure is only logged as a warning.
+pub fn apply_atomic(set: Set) -> Result<()> {
+    let original = with_saved_state(|| {
+        get_caps_process()?;
+        save_state().ok_or(Error::SaveState)
+    })?;
+
+    let err = match apply(set) {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+
+    let configured = save_state().ok_or(Error::SaveState)?;
+    restore_state(original);
+    if let Err(e) = apply(set) {
+        warn!("failed to roll back capabilities after {}: {}", err, e);
+    }
+    restore_state(configured);
+
+    Err(err)
+}
+
+/// The outcome of `apply_detailed` for each part of the state, `None` for
+/// the parts that weren't selected.
+#[derive(Debug)]
+pub struct ApplyReport {
+    pub caps: Option<Result<()>>,
+    pub bounds: Option<Result<()>>,
+    pub ambient: Option<Result<()>>,
+}
+
+impl ApplyReport {
+    /// Returns whether every selected part was applied.
+    pub fn is_ok(&self) -> bool {
+        [&self.caps, &self.bounds, &self.ambient]
+            .iter()
+            .all(|r| !matches!(r, Some(Err(_))))
+    }
+}
+
+/// Like `apply`, but applies `Set::CAPS`, `Set::BOUNDS` and `Set::AMBIENT`
+/// one after the other, as selected by `set`, and reports the result of each
+/// rather than stopping at the first failure.
+///
+/// This tells, e.g., capabilities that were applied apart from a bounding
+/// set that couldn't be.
+pub fn apply_detailed(set: Set) -> ApplyReport {
+    let part = |part: Set| {
+        if set.contains(part) {
+            Some(apply(part))
+        } else {
+            None
+        }
+    };
+
+    ApplyReport {
+        caps: part(Set::CAPS),
+        bounds: part(Set::BOUNDS),
+        ambient: part(Set::AMBIENT),
+    }
+}
+
+/// Returns `Error::MultipleThreads` if the process runs more than one
+/// thread, logging a warning as well.
+///
+/// Capabilities are per thread: dropping them in a multi-threaded process
+/// only affects the calling thread, and the other ones keep their
+/// privileges. Calling this before dropping capabilities catches that.
+pub fn warn_if_multithreaded() -> Result<()> {
+    let path = Path::new("/proc/self/task");
+    let count = std::fs::read_dir(path)
+        .map_err(|_| Error::ReadDirectory(path.to_path_buf()))?
+        .count();
+
+    if count > 1 {
+        warn!(
+            "the process runs {} threads, capabilities only change for the calling one",
+            count
+        );
+        return Err(Error::MultipleThreads(count));
+    }
+
+    Ok(())
+}
+
+/// Like `apply`, but makes sure the selected parts end up applied to every
+/// thread of the process.
+///
+/// A thread can only change its own capabilities, and having the other
+/// threads apply them behind their back, e.g. from a signal handler, isn't
+/// safe. So this only applies them when the calling thread is the only one,
+/// and returns `Error::MultipleThreads` otherwise, see
+/// `warn_if_multithreaded`. Capabilities should therefore be configured
+/// before spawning threads, which then inherit them.
+pub fn apply_all_threads(set: Set) -> Result<()> {
+    warn_if_multithreaded()?;
+    apply(set)
+}
+
+/// Gives the calling process the capabilities of process `pid`, for the
+/// parts selected by `set`, e.g. to make a worker mirror a template process.
+///
+/// The capabilities of `pid` are read with the libcap-ng state saved and
+/// restored around it. They're then applied to the calling process, whose
+/// unselected parts are left as they are. Afterwards, libcap-ng targets the
+/// calling process, and its state holds the applied configuration.
+pub fn adopt_caps_from(pid: Pid, set: Set) -> Result<()> {
+    let caps = with_saved_state(|| {
+        setpid(pid);
+        get_caps_process()?;
+        Ok(ProcessCaps::from_state())
+    })?;
+
+    setpid(0);
+    get_caps_process()?;
+    clear(set);
+
+    let mut which = Type::empty();
+    if set.contains(Set::CAPS) {
+        which |= Type::EFFECTIVE | Type::PERMITTED | Type::INHERITABLE;
+    }
+    if set.contains(Set::BOUNDS) {
+        which |= Type::BOUNDING_SET;
+    }
+    if set.contains(Set::AMBIENT) {
+        which |= Type::AMBIENT;
+    }
+    let mut updates = Vec::new();
+    for t in which {
+        updates.extend(caps.get(t).iter().map(|capability| CUpdate {
+            action: Action::ADD,
+            cap_type: t,
+            capability,
+        }));
+    }
+    update(updates)?;
+
+    apply(set)
+}
+
+/// Drops `caps` from the bounding set of the target process, returning the
+/// ones that were actually dropped, sorted by ID.
+///
+/// Capabilities already missing from the bounding set are skipped, so
+/// calling this again, e.g. on a configuration reload, drops nothing and
+/// returns an empty vector. The process capabilities are loaded first, and
+/// left in the libcap-ng state, updated, afterwards.
+pub fn drop_bounding_once(caps: &[Capability]) -> Result<Vec<Capability>> {
+    get_caps_process()?;
+    let bounding = ProcessCaps::from_state().bounding;
+    let mut present: Vec<Capability> = caps
+        .iter()
+        .copied()
+        .filter(|&cap| bounding.contains(cap))
+        .collect();
+    present.sort_unstable();
+    present.dedup();
+    if present.is_empty() {
+        return Ok(present);
+    }
+
+    update(
+        present
+            .iter()
+            .map(|&capability| CUpdate {
+                action: Action::DROP,
+                cap_type: Type::BOUNDING_SET,
+                capability,
+            })
+            .collect(),
+    )?;
+    apply(Set::BOUNDS)?;
+
+    Ok(present)
+}
+
+/// Passes `select_bits` to `capng_apply` as is, for select values `Set`
+/// doesn't model yet.
+///
+/// # Safety
+///
+/// Unlike `apply`, this doesn't check the bits, nor whether the linked
+/// libcap-ng supports what they select, so the caller must know what the
+/// underlying C constants mean for that libcap-ng version.
+pub unsafe fn apply_raw(select_bits: u32) -> Result<()> {
+    ffi_span!("apply_raw", select_bits);
+    let ret = bindings::capng_apply(select_bits);
 
     if ret == 0 {
         Ok(())
@@ -227,7 +1025,107 @@ pub fn apply(set: Set) -> Result<()> {
     }
 }
 
+/// Keeps `caps` only as ambient capabilities, so they're the only ones
+/// passed on to programs executed afterwards.
+///
+/// The kernel only allows a capability in the ambient set if it's also in
+/// the permitted and inheritable sets, so `caps` are added to those too.
+/// Everything else in the effective, permitted, inheritable and ambient sets
+/// is dropped. The bounding set is left untouched.
+pub fn set_ambient_only(caps: &[Capability]) -> Result<()> {
+    clear(Set::CAPS | Set::AMBIENT);
+    update(
+        caps.iter()
+            .map(|&capability| CUpdate {
+                action: Action::ADD,
+                cap_type: Type::PERMITTED | Type::INHERITABLE | Type::AMBIENT,
+                capability,
+            })
+            .collect(),
+    )?;
+
+    apply(Set::CAPS | Set::AMBIENT)
+}
+
+/// Keeps only `caps`, in the effective, permitted and bounding sets, and
+/// drops every other capability, including inheritable and ambient ones.
+///
+/// Changing the bounding set needs `CAP_SETPCAP`, which is dropped too unless
+/// listed in `caps`.
+///
+/// `caps` can be given by ID or by name, like `&["chown", "kill"]`, see
+/// `ToCapability`. Names are all resolved before anything is changed.
+pub fn keep_only<C: ToCapability>(caps: &[C]) -> Result<()> {
+    let caps = caps
+        .iter()
+        .map(ToCapability::to_capability)
+        .collect::<Result<Vec<_>>>()?;
+
+    clear(Set::BOTH | Set::AMBIENT);
+    update(
+        caps.into_iter()
+            .map(|capability| CUpdate {
+                action: Action::ADD,
+                cap_type: Type::EFFECTIVE | Type::PERMITTED | Type::BOUNDING_SET,
+                capability,
+            })
+            .collect(),
+    )?;
+
+    apply(Set::BOTH)
+}
+
+/// Keeps only `caps` like `keep_only` does if the process is privileged,
+/// that is, running as root or with any effective capability, returning
+/// whether it did.
+///
+/// Unprivileged processes have nothing to drop, so this does nothing and
+/// returns `false` for them instead of failing, which suits libraries that
+/// don't know how they'll be started.
+pub fn drop_if_privileged(keep: &[Capability]) -> Result<bool> {
+    get_caps_process()?;
+    // Safe because this doesn't modify any local memory.
+    if unsafe { libc::geteuid() } != 0 && capabilities_in(Type::EFFECTIVE).is_empty() {
+        return Ok(false);
+    }
+
+    keep_only(keep)?;
+    Ok(true)
+}
+
+/// Drops all the ambient capabilities, so that programs executed afterwards
+/// don't inherit any, leaving the other vectors untouched.
+///
+/// This is the counterpart to `PR_CAP_AMBIENT_CLEAR_ALL`. `change_id` can do
+/// the same as part of changing IDs with `Flags::CLEAR_AMBIENT`.
+pub fn clear_ambient() -> Result<()> {
+    support::check_ambient("ambient")?;
+    clear(Set::AMBIENT);
+    apply(Set::AMBIENT)
+}
+
+/// Checks that every capability in `caps`, meant to be raised in the ambient
+/// set, is also in the permitted and inheritable sets of the current
+/// libcap-ng state, as the kernel requires.
+///
+/// Calling this before `apply` turns an opaque `EPERM` from the kernel into
+/// `Error::AmbientPreconditionUnmet` naming the offending capability.
+pub fn validate_ambient_preconditions(caps: &[Capability]) -> Result<()> {
+    match caps.iter().find(|&&cap| {
+        !have_capability(Type::PERMITTED, cap) || !have_capability(Type::INHERITABLE, cap)
+    }) {
+        Some(&cap) => Err(Error::AmbientPreconditionUnmet(cap)),
+        None => Ok(()),
+    }
+}
+
 pub fn lock() -> Result<()> {
+    ffi_span!("lock");
+    if dry_run() {
+        info!("dry run: not locking the securebits");
+        return Ok(());
+    }
+
     // Safe because this doesn't modify any local memory.
     let ret = unsafe { bindings::capng_lock() };
 
@@ -238,30 +1136,234 @@ pub fn lock() -> Result<()> {
     }
 }
 
-pub fn change_id(uid: i32, gid: i32, flags: Flags) -> Result<()> {
+/// Sets exactly the securebits in `bits` and locks them, so they can't be
+/// changed afterwards, leaving the other securebits as they are.
+///
+/// For example, `Securebits::NOROOT` stops root from regaining capabilities
+/// through exec while keeping `KEEP_CAPS` mutable, unlike `lock`, which sets
+/// and locks several securebits at once. This needs `CAP_SETPCAP` and only
+/// affects the calling thread.
+pub fn lock_bits(bits: Securebits) -> Result<()> {
+    if dry_run() {
+        info!("dry run: not setting and locking the securebits {:?}", bits);
+        return Ok(());
+    }
+
+    // Each lock bit sits right above the bit it locks.
+    let bits = bits.bits() | (bits.bits() & 0x55) << 1;
+
+    // Safe because these don't modify any local memory.
+    let current = unsafe { libc::prctl(libc::PR_GET_SECUREBITS) };
+    if current < 0 {
+        return Err(Error::GetSecurebits);
+    }
+    let ret = unsafe { libc::prctl(libc::PR_SET_SECUREBITS, current as u32 | bits) };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(Error::LockCapabilities)
+    }
+}
+
+/// Returns whether the securebits lock `NOROOT`, as `lock` and
+/// `lock_bits(Securebits::NOROOT)` do, so that root can't regain
+/// capabilities through exec anymore. Only the calling thread is checked.
+pub fn capability_transitions_locked() -> Result<bool> {
+    // Safe because this doesn't modify any local memory.
+    let bits = unsafe { libc::prctl(libc::PR_GET_SECUREBITS) };
+    if bits < 0 {
+        return Err(Error::GetSecurebits);
+    }
+
+    Ok(Securebits::from_bits_truncate(bits as u32).contains(Securebits::NOROOT_LOCKED))
+}
+
+/// Returns whether the no_new_privs flag is set, in which case exec can't
+/// grant any privilege, like capabilities from a set-user-ID binary or file
+/// capabilities. Only the calling thread is checked.
+pub fn no_new_privs() -> Result<bool> {
+    // Safe because this doesn't modify any local memory.
+    let ret = unsafe { libc::prctl(libc::PR_GET_NO_NEW_PRIVS, 0, 0, 0, 0) };
+    if ret < 0 {
+        return Err(Error::GetNoNewPrivs);
+    }
+
+    Ok(ret == 1)
+}
+
+/// Makes `caps` unreachable for good: drops them from the bounding set of
+/// the calling thread, then sets the no_new_privs flag, so that not even a
+/// set-user-ID binary or file capabilities can grant them again. Its
+/// children inherit both.
+///
+/// The flag is set even if dropping `caps` fails. If either step fails,
+/// `Error::ForbidForever` is returned, telling both outcomes apart, so the
+/// caller knows whether exec can still grant privileges. Like
+/// `drop_bounding_once`, the process capabilities are loaded first, and
+/// left in the libcap-ng state, updated, afterwards.
+pub fn forbid_forever(caps: &[Capability]) -> Result<()> {
+    let dropped = drop_bounding_once(caps);
+
+    let no_new_privs = if dry_run() {
+        info!("dry run: not setting the no_new_privs flag");
+        true
+    } else {
+        // Safe because this doesn't modify any local memory.
+        unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) == 0 }
+    };
+
+    match (dropped, no_new_privs) {
+        (Ok(_), true) => Ok(()),
+        (dropped, no_new_privs) => Err(Error::ForbidForever {
+            bounding: dropped.err().map(Box::new),
+            no_new_privs,
+        }),
+    }
+}
+
+pub fn change_id(uid: Uid, gid: Gid, flags: Flags) -> Result<()> {
+    change_id_raw(uid, gid, flags).map_err(|_| Error::ChangeTargetId)
+}
+
+/// What `capng_change_id` returned on failure, along with `errno`.
+type ChangeIdFailure = (c_int, Option<i32>);
+
+/// What `capng_change_id` returns when initializing or dropping the
+/// supplementary groups failed, in which case `errno` is the one left by
+/// `initgroups` or `setgroups`.
+const CHANGE_ID_SUPP_GRP_FAILED: c_int = -5;
+
+/// Does the work of `change_id`, but returns the failure as is, with `errno`
+/// read right after `capng_change_id`, before anything else, like the
+/// tracing span exiting, can clobber it.
+fn change_id_raw(uid: Uid, gid: Gid, flags: Flags) -> std::result::Result<(), ChangeIdFailure> {
+    ffi_span!("change_id", uid, gid, ?flags);
+    if dry_run() {
+        info!(
+            "dry run: not changing to uid {} and gid {} with {:?}",
+            uid, gid, flags
+        );
+        return Ok(());
+    }
+
+    bump_generation();
     // Safe because this doesn't modify any local memory.
-    let ret = unsafe { bindings::capng_change_id(uid, gid, flags.bits() as u32) };
+    let ret = unsafe { bindings::capng_change_id(uid, gid, flags.bits()) };
 
     if ret == 0 {
         Ok(())
     } else {
-        Err(Error::ChangeTargetId)
+        Err((ret, io::Error::last_os_error().raw_os_error()))
+    }
+}
+
+/// Like `change_id`, but retries up to `attempts` times in total when the
+/// call fails transiently, waiting a little longer before each new attempt.
+///
+/// Looking up supplementary groups for `Flags::INIT_SUPP_GRP` may go through
+/// network services like LDAP, which can fail temporarily. Only failures
+/// initializing the supplementary groups, as told by what `capng_change_id`
+/// returns, with `errno` set to `EAGAIN`, `EINTR` or `ETIMEDOUT` are
+/// retried, while any other failure is returned right away.
+pub fn change_id_retry(uid: Uid, gid: Gid, flags: Flags, attempts: u32) -> Result<()> {
+    retry_change_id(attempts, || change_id_raw(uid, gid, flags))
+}
+
+/// Runs `attempt` until it succeeds, fails for good, or ran `attempts` times,
+/// see `change_id_retry`.
+fn retry_change_id(
+    attempts: u32,
+    mut attempt: impl FnMut() -> std::result::Result<(), ChangeIdFailure>,
+) -> Result<()> {
+    let mut delay = Duration::from_millis(10);
+    for n in 1.. {
+        let (ret, errno) = match attempt() {
+            Ok(()) => return Ok(()),
+            Err(failure) => failure,
+        };
+
+        let transient = ret == CHANGE_ID_SUPP_GRP_FAILED
+            && matches!(errno, Some(libc::EAGAIN | libc::EINTR | libc::ETIMEDOUT));
+        if !transient || n >= attempts {
+            return Err(Error::ChangeTargetId);
+        }
+        thread::sleep(delay);
+        delay *= 2;
     }
+
+    unreachable!()
 }
 
+/// Changes to `uid` and `gid` while keeping only `caps`, which are also
+/// raised in the inheritable and ambient sets so that programs executed
+/// afterwards still get them.
+///
+/// All other capabilities are dropped. `flags` are passed on to `change_id`,
+/// which keeps the capabilities across the ID change. As it doesn't touch
+/// the ambient set, that's applied last, once running as `uid`.
+pub fn drop_to_user(uid: Uid, gid: Gid, keep: &[Capability], flags: Flags) -> Result<()> {
+    support::check_ambient("ambient")?;
+    clear(Set::CAPS | Set::AMBIENT);
+    update(
+        keep.iter()
+            .map(|&capability| CUpdate {
+                action: Action::ADD,
+                cap_type: Type::EFFECTIVE | Type::PERMITTED | Type::INHERITABLE | Type::AMBIENT,
+                capability,
+            })
+            .collect(),
+    )?;
+
+    change_id(uid, gid, flags)?;
+    apply(Set::AMBIENT)
+}
+
+/// Loads the file capabilities of `file` into the libcap-ng state, replacing
+/// whatever process capabilities were being configured.
+///
+/// Prefer `read_file_caps`, which leaves the libcap-ng state untouched, to
+/// merely inspect a file.
 pub fn get_caps_file(file: File) -> Result<()> {
+    ffi_span!("get_caps_file", fd = file.as_raw_fd());
+    bump_generation();
     // Safe because this doesn't modify any local memory and doesn't alter
     // the offset of the file descriptor.
     let ret = unsafe { bindings::capng_get_caps_fd(file.as_raw_fd()) };
 
     if ret == 0 {
+        set_loaded();
         Ok(())
     } else {
         Err(Error::GetCapsFile(file))
     }
 }
 
+/// Lists the capabilities requested by the file capabilities of `file`
+/// that the target process could grant, that is, the ones also present in
+/// its permitted set.
+///
+/// Both the file and process capabilities are loaded to compute this, but
+/// the previous libcap-ng state is restored before returning.
+pub fn grantable_file_caps(file: &File) -> Result<Vec<Capability>> {
+    with_saved_state(|| {
+        get_caps_raw_fd(file.as_raw_fd())?;
+        let requested: Vec<Capability> = known_capabilities()
+            .filter(|&cap| {
+                have_capability(Type::PERMITTED, cap) || have_capability(Type::INHERITABLE, cap)
+            })
+            .collect();
+
+        get_caps_process()?;
+        Ok(requested
+            .into_iter()
+            .filter(|&cap| have_capability(Type::PERMITTED, cap))
+            .collect())
+    })
+}
+
 pub fn apply_caps_fd(file: File) -> Result<()> {
+    ffi_span!("apply_caps_fd", fd = file.as_raw_fd());
     // Safe because this doesn't modify any local memory and doesn't alter
     // the offset of the file descriptor.
     let ret = unsafe { bindings::capng_apply_caps_fd(file.as_raw_fd()) };
@@ -273,114 +1375,606 @@ pub fn apply_caps_fd(file: File) -> Result<()> {
     }
 }
 
+/// Returns whether the `set` vectors of the current libcap-ng state hold
+/// none, some or all capabilities.
+///
+/// Fails with `Error::CapsNotLoaded` if no capabilities were loaded on the
+/// calling thread yet.
 pub fn have_capabilities(set: Set) -> Result<HaveCapsResult> {
+    ffi_span!("have_capabilities", ?set);
+    check_loaded()?;
     // Safe because this doesn't modify any local memory.
-    let ret = unsafe { bindings::capng_have_capabilities(set.bits() as u32) };
+    let ret = unsafe { bindings::capng_have_capabilities(set.bits()) };
 
     HaveCapsResult::try_from(ret)
 }
 
+/// Like `have_capabilities`, but for the permitted set.
 pub fn have_permitted_capabilities() -> Result<HaveCapsResult> {
+    ffi_span!("have_permitted_capabilities");
+    check_loaded()?;
     // Safe because this doesn't modify any local memory.
     let ret = unsafe { bindings::capng_have_permitted_capabilities() };
 
     HaveCapsResult::try_from(ret)
 }
 
+/// Like `have_capabilities`, but succeeds only if the `set` vectors hold all
+/// capabilities, and fails with `Error::InsufficientCapabilities` carrying
+/// the actual result otherwise.
+///
+/// This suits startup checks that a privileged subsystem runs with full
+/// privileges before initializing.
+pub fn require_full(set: Set) -> Result<()> {
+    match have_capabilities(set)? {
+        HaveCapsResult::FULL => Ok(()),
+        result => Err(Error::InsufficientCapabilities(result)),
+    }
+}
+
+/// Returns whether `capability` is in the `which` vector of the current
+/// libcap-ng state, answering from the cache if `enable_caps_cache` was
+/// called on the calling thread.
 pub fn have_capability(which: Type, capability: Capability) -> bool {
+    state::cached_capability(which, capability)
+        .unwrap_or_else(|| have_capability_uncached(which, capability))
+}
+
+/// Like `have_capability`, but fails with `Error::CapsNotLoaded` if no
+/// capabilities were loaded on the calling thread yet, instead of reporting
+/// them all as absent.
+///
+/// Unlike `have_capability`, `capability` can also be given by name, see
+/// `ToCapability`, in which case unknown names are reported as errors.
+pub fn try_have_capability(which: Type, capability: impl ToCapability) -> Result<bool> {
+    let capability = capability.to_capability()?;
+    check_loaded()?;
+    Ok(have_capability(which, capability))
+}
+
+/// Like `have_capability`, but always asks libcap-ng.
+pub(crate) fn have_capability_uncached(which: Type, capability: Capability) -> bool {
+    ffi_span!("have_capability", ?which, capability);
     // Safe because this doesn't modify any local memory.
-    let ret = unsafe { bindings::capng_have_capability(which.bits() as u32, capability) };
+    let ret = unsafe { bindings::capng_have_capability(which.bits(), capability) };
 
-    ret == 1
+    // libcap-ng returns 0 when the capability is missing, or when it can't
+    // tell, e.g. for an unknown ID, and a positive value otherwise.
+    ret > 0
 }
 
-pub fn print_caps_numeric(print: Print, set: Set) -> Option<String> {
-    // Safe because it doesn't modify any local memory, we check the buffer and
-    // move its contents to local memory.
-    let buffer = unsafe { bindings::capng_print_caps_numeric(print as u32, set.bits() as u32) };
-    match print {
-        Print::STDOUT => None,
-        Print::BUFFER => {
-            if buffer.is_null() {
-                None
-            } else {
-                // Safe as long capng_print_caps_numeric behaves as expected.
-                let caps = unsafe { CStr::from_ptr(buffer).to_string_lossy().into_owned() };
-                unsafe { libc::free(buffer as *mut ::core::ffi::c_void) };
-                Some(caps)
-            }
+/// Lists the capabilities present in the `which` vector of the current
+/// libcap-ng state, in ascending order. `which` must name a single vector.
+///
+/// Like `have_capability`, this reports the state last loaded or configured,
+/// so call `get_caps_process` first to inspect the process.
+pub fn capabilities_in(which: Type) -> Vec<Capability> {
+    known_capabilities()
+        .filter(|&cap| have_capability(which, cap))
+        .collect()
+}
+
+/// Lists the capabilities in the bounding set of the current libcap-ng state.
+///
+/// Unlike the other vectors, the bounding set isn't a grant: it's the ceiling
+/// of capabilities the process, and anything it executes, could ever acquire.
+/// Once dropped from it, a capability can't be regained, which makes this the
+/// set to look at when auditing what a process could escalate to.
+pub fn bounding_set() -> Vec<Capability> {
+    capabilities_in(Type::BOUNDING_SET)
+}
+
+/// Checks that the target process holds all of `caps` in its effective set,
+/// returning `Error::MissingCapability` for the first one it doesn't.
+///
+/// This is meant as a guard at the top of privileged routines and tests, so
+/// they fail early with an actionable error instead of deep in a C call. The
+/// previous libcap-ng state is restored before returning.
+pub fn require_capabilities(caps: &[Capability]) -> Result<()> {
+    with_saved_state(|| {
+        get_caps_process()?;
+        match caps
+            .iter()
+            .find(|&&cap| !have_capability(Type::EFFECTIVE, cap))
+        {
+            Some(&cap) => Err(Error::MissingCapability(cap)),
+            None => Ok(()),
         }
+    })
+}
+
+/// Returns whether the target process could raise `cap` into its effective
+/// set, which is the case when it's in the permitted set.
+///
+/// Any failure reading the process's capabilities is reported as `false`.
+/// The previous libcap-ng state is restored before returning.
+pub fn can_raise(cap: Capability) -> bool {
+    with_saved_state(|| {
+        get_caps_process()?;
+        Ok(have_capability(Type::PERMITTED, cap))
+    })
+    .unwrap_or(false)
+}
+
+/// Lists the capabilities the target process could raise but hasn't, that
+/// is, the ones in its permitted set but not in its effective set, in
+/// ascending order.
+///
+/// The previous libcap-ng state is restored before returning.
+pub fn latent_capabilities() -> Result<Vec<Capability>> {
+    with_saved_state(|| {
+        get_caps_process()?;
+        Ok(known_capabilities()
+            .filter(|&cap| {
+                have_capability(Type::PERMITTED, cap) && !have_capability(Type::EFFECTIVE, cap)
+            })
+            .collect())
+    })
+}
+
+/// Checks that the `which` vectors of the target process hold exactly
+/// `expected`, no more and no fewer, regardless of order and duplicates.
+///
+/// With several vectors in `which`, each of them must match. The previous
+/// libcap-ng state is restored before returning.
+pub fn caps_equal(which: Type, expected: &[Capability]) -> Result<bool> {
+    let mut expected = expected.to_vec();
+    expected.sort_unstable();
+    expected.dedup();
+
+    with_saved_state(|| {
+        get_caps_process()?;
+        Ok(which.into_iter().all(|t| capabilities_in(t) == expected))
+    })
+}
+
+/// Returns whether the process holds the capabilities needed to change its
+/// capabilities and IDs freely, that is, `CAP_SETPCAP`, `CAP_SETUID` and
+/// `CAP_SETGID` in its effective set.
+///
+/// This is meant for test suites to skip tests that need privileges when
+/// running unprivileged, e.g. in CI sandboxes, instead of failing.
+pub fn running_privileged() -> bool {
+    require_capabilities(&PRIVILEGED_CAPS).is_ok()
+}
+
+/// Formats the capabilities in the `set` vectors as hexadecimal masks.
+///
+/// With `Print::BUFFER`, returns `None` only if libcap-ng fails to format
+/// them, e.g. because its state isn't initialized. With `Print::STDOUT`, the
+/// masks are printed and `None` is always returned.
+pub fn print_caps_numeric(print: Print, set: Set) -> Option<String> {
+    // Safe because it doesn't modify any local memory, we check the buffer and
+    // move its contents to local memory.
+    let buffer = unsafe { bindings::capng_print_caps_numeric(print as u32, set.bits()) };
+    match print {
+        Print::STDOUT => None,
+        // Safe as long capng_print_caps_numeric behaves as expected.
+        Print::BUFFER => unsafe { take_print_buffer(buffer) },
     }
 }
 
+/// Formats the names of the capabilities in the `which` vector, separated by
+/// commas.
+///
+/// With `Print::BUFFER`, returns `Some` with an empty string if the vector
+/// holds no capabilities, where libcap-ng itself would give `"none"`, and
+/// `None` only if libcap-ng fails to format it, e.g. because its state isn't
+/// initialized. With `Print::STDOUT`, the names are printed as libcap-ng
+/// formats them and `None` is always returned.
 pub fn print_caps_text(print: Print, which: Type) -> Option<String> {
     // Safe because it doesn't modify any local memory, we check the buffer and
     // move its contents to local memory.
-    let buffer = unsafe { bindings::capng_print_caps_text(print as u32, which.bits() as u32) };
+    let buffer = unsafe { bindings::capng_print_caps_text(print as u32, which.bits()) };
     match print {
         Print::STDOUT => None,
-        Print::BUFFER => {
-            if buffer.is_null() {
-                None
+        // Safe as long capng_print_caps_text behaves as expected.
+        Print::BUFFER => unsafe { take_print_buffer(buffer) }.map(|caps| {
+            if caps == "none" {
+                String::new()
             } else {
-                // Safe as long capng_print_caps_text behaves as expected.
-                let caps = unsafe { CStr::from_ptr(buffer).to_string_lossy().into_owned() };
-                unsafe { libc::free(buffer as *mut ::core::ffi::c_void) };
-                Some(caps)
+                caps
             }
-        }
+        }),
     }
 }
 
-pub fn name_to_capability(name: &str) -> Result<Capability> {
-    let cstr = CString::new(name).map_err(|_| Error::ConvertCapabilityName)?;
+/// Moves the contents of a buffer returned by the libcap-ng print functions
+/// to a `String` and frees it, or returns `None` if it's null.
+///
+/// # Safety
+///
+/// `buffer` must be null or a nul-terminated string allocated by libcap-ng,
+/// which isn't used anymore after this call.
+unsafe fn take_print_buffer(buffer: *mut c_char) -> Option<String> {
+    if buffer.is_null() {
+        return None;
+    }
+    let caps = CStr::from_ptr(buffer).to_string_lossy().into_owned();
+    support::free_buffer(buffer as *mut ::core::ffi::c_void);
+    Some(caps)
+}
 
-    // Safe because this doesn't modify any local memory and we have converted
-    // name to a CString.
-    let cap_id: i32 = unsafe { bindings::capng_name_to_capability(cstr.as_ptr() as *const c_char) };
-    if cap_id < 0 {
-        return Err(Error::GetCapabilityId(name.to_string()));
+/// Writes the capabilities in the `which` vector to the standard error, as
+/// a line formatted like `print_caps_text` does, so diagnostics don't mix
+/// with the program's output.
+///
+/// Nothing is written if libcap-ng fails to format the vector, and errors
+/// writing to the standard error are ignored.
+pub fn eprint_caps_text(which: Type) {
+    if let Some(caps) = print_caps_text(Print::BUFFER, which) {
+        let _ = writeln!(io::stderr(), "{}", caps);
     }
+}
 
-    Ok(cap_id as Capability)
+/// Formats the capabilities in the `which` vector like `print_caps_text`
+/// does, but in pure Rust and always sorted by capability ID, so the output
+/// doesn't depend on the libcap-ng version.
+///
+/// Returns `None` if `which` doesn't name a single vector.
+pub fn print_caps_text_sorted(which: Type) -> Option<String> {
+    if which.bits().count_ones() != 1 {
+        return None;
+    }
+
+    let names = capabilities_in(which)
+        .into_iter()
+        .map(capability_to_name)
+        .collect::<Result<Vec<String>>>()
+        .ok()?;
+
+    Some(names.join(", "))
 }
 
-pub fn capability_to_name(capability: Capability) -> Result<String> {
-    // Safe because this doesn't modify any local memory.
-    let name_ptr = unsafe { bindings::capng_capability_to_name(capability) };
-    if name_ptr.is_null() {
-        return Err(Error::NameToCapability(capability));
+/// Resolves every name in `names` to its capability ID, in order, failing
+/// with `Error::GetCapabilityId` for the first unknown name.
+pub fn capabilities_from_names(names: &[&str]) -> Result<Vec<Capability>> {
+    names.iter().map(|name| name_to_capability(name)).collect()
+}
+
+/// Like `capabilities_from_names`, but skips unknown names, logging a warning
+/// for each of them, e.g. to accept allowlists written for newer kernels.
+pub fn capabilities_from_names_lenient(names: &[&str]) -> Vec<Capability> {
+    names
+        .iter()
+        .filter_map(|name| match name_to_capability(name) {
+            Ok(cap) => Some(cap),
+            Err(_) => {
+                warn!("skipping unknown capability {:?}", name);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Parses a comma-separated list of capability names, such as the output of
+/// `print_caps_text`, into their IDs.
+///
+/// Names are matched case-insensitively and may carry a `cap_` prefix. Empty
+/// entries are skipped, and `none` stands for an empty list.
+pub fn parse_caps_text(text: &str) -> Result<Vec<Capability>> {
+    let mut caps = Vec::new();
+    for name in text.split(',').map(str::trim) {
+        if name.is_empty() || name.eq_ignore_ascii_case("none") {
+            continue;
+        }
+        let name = match name.get(..4) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("cap_") => &name[4..],
+            _ => name,
+        };
+        caps.push(name_to_capability(name)?);
     }
-    // Only safe if capng_capability_to_name behaves properly.
-    let name = unsafe { CStr::from_ptr(name_ptr).to_string_lossy().into_owned() };
 
-    Ok(name)
+    Ok(caps)
 }
 
 pub fn save_state() -> Option<CapngState> {
+    ffi_span!("save_state");
     // Safe because this doesn't modify any local memory and we check opaque.
     let opaque = unsafe { bindings::capng_save_state() };
 
     if opaque.is_null() {
         None
     } else {
-        Some(CapngState { opaque })
+        Some(CapngState {
+            opaque: Cell::new(opaque),
+            loaded: LOADED.with(|l| l.get()),
+            bounding_adds: BOUNDING_ADDS.with(|b| b.get()),
+        })
     }
 }
 
 pub fn restore_state(state: CapngState) {
+    ffi_span!("restore_state");
+    bump_generation();
+    LOADED.with(|l| l.set(state.loaded));
+    BOUNDING_ADDS.with(|b| b.set(state.bounding_adds));
     // Safe because this only consumes state.opaque, and we're consuming
     // CapngState here.
     unsafe {
-        let mut opaque: *mut ::std::os::raw::c_void = state.opaque;
+        let mut opaque: *mut ::std::os::raw::c_void = state.opaque.get();
         let opaque_ptr: *mut *mut ::std::os::raw::c_void = &mut opaque;
         bindings::capng_restore_state(opaque_ptr)
     };
 }
 
+/// Checks whether `state` holds the capabilities in `caps`, to verify that
+/// saving and restoring preserves them.
+///
+/// `state` is restored into libcap-ng to inspect it, after which the
+/// previous libcap-ng state is put back. `state` remains usable.
+pub fn state_matches(state: &CapngState, caps: &ProcessCaps) -> bool {
+    with_saved_state(|| {
+        // Safe because capng_restore_state only consumes the blob, and we
+        // immediately replace it below.
+        unsafe {
+            let mut opaque = state.opaque.get();
+            bindings::capng_restore_state(&mut opaque);
+        }
+        bump_generation();
+        // Restoring freed the blob, so hand a fresh copy back to state. Should
+        // saving fail, state is left empty and restoring it does nothing.
+        // Safe because this doesn't modify any local memory.
+        state.opaque.set(unsafe { bindings::capng_save_state() });
+
+        Ok(ProcessCaps::from_state() == *caps)
+    })
+    .unwrap_or(false)
+}
+
+/// Checks that the linked libcap-ng saves and restores its state faithfully,
+/// which the helpers that bracket their work with `save_state` and
+/// `restore_state` rely on.
+///
+/// A known pattern of capabilities is configured, saved, cleared and
+/// restored, and `Error::SelfCheck` is returned if it doesn't come back, e.g.
+/// because of an ABI mismatch or a broken library. Nothing is applied, and
+/// the previous libcap-ng state is restored before returning, so a daemon
+/// can call this at startup.
+pub fn self_check() -> Result<()> {
+    with_saved_state(|| {
+        let pattern = [
+            (Type::EFFECTIVE, &[cap::CHOWN, cap::NET_ADMIN][..]),
+            (
+                Type::PERMITTED,
+                &[cap::CHOWN, cap::KILL, cap::NET_ADMIN][..],
+            ),
+            (Type::INHERITABLE, &[cap::SETUID][..]),
+            (Type::BOUNDING_SET, &[cap::KILL, cap::SYS_ADMIN][..]),
+        ];
+
+        clear(Set::BOTH);
+        update(
+            pattern
+                .iter()
+                .flat_map(|&(which, caps)| {
+                    caps.iter().map(move |&capability| CUpdate {
+                        action: Action::ADD,
+                        cap_type: which,
+                        capability,
+                    })
+                })
+                .collect(),
+        )?;
+        if pattern
+            .iter()
+            .any(|&(which, caps)| capabilities_in(which) != caps)
+        {
+            return Err(Error::SelfCheck);
+        }
+        let expected = ProcessCaps::from_state();
+
+        let state = save_state().ok_or(Error::SaveState)?;
+        clear(Set::BOTH);
+        restore_state(state);
+
+        if ProcessCaps::from_state() == expected {
+            Ok(())
+        } else {
+            Err(Error::SelfCheck)
+        }
+    })
+}
+
+/// Resets the state cached by the crate and by libcap-ng, then loads the
+/// capabilities of the calling process, for use in a child right after
+/// `fork`.
+///
+/// A forked child inherits the parent's caches, so it should call this
+/// before using the crate, and before anything else if the parent was
+/// configuring capabilities when it forked. The caches reset are:
+///
+/// - the lookups of libcap-ng symbols that not every version provides, as
+///   done by `supports_ambient` and `get_rootid`,
+/// - the target process of libcap-ng, which is reset to the calling process.
+pub fn reset_after_fork() -> Result<()> {
+    support::reset_cache();
+    setpid(0);
+    get_caps_process()
+}
+
+/// Turns the dry-run mode on or off for the whole process.
+///
+/// In dry-run mode, `apply`, `change_id` and `lock`, and the helpers built
+/// on them, log what they would do at the info level and return `Ok(())`
+/// without changing anything, so a hardening configuration can be previewed
+/// safely against a live process. The libcap-ng state is still updated as
+/// usual, only the kernel isn't called.
+pub fn set_dry_run(enabled: bool) {
+    DRY_RUN.store(enabled, Ordering::Relaxed);
+}
+
+fn dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Returns the generation of the libcap-ng state of the calling thread,
+/// which is bumped by every call that may change it, like `clear`, `update`,
+/// `get_caps_process` or `restore_state`.
+///
+/// Comparing generations tells whether something changed the state in the
+/// meantime, see `ProcessCaps::is_stale`. Helpers that save and restore the
+/// state around their work bump it too.
+pub fn caps_generation() -> u64 {
+    GENERATION.with(|g| g.get())
+}
+
+fn bump_generation() {
+    GENERATION.with(|g| g.set(g.get().wrapping_add(1)));
+}
+
+fn reset_bounding_adds() {
+    BOUNDING_ADDS.with(|b| b.set(CapabilitySet::empty()));
+}
+
+/// Keeps track of the capabilities added to the bounding set by an update.
+fn track_bounding_update(action: Action, which: Type, capability: Capability) {
+    if !which.contains(Type::BOUNDING_SET) {
+        return;
+    }
+    BOUNDING_ADDS.with(|b| {
+        let mut adds = b.get();
+        match action {
+            Action::ADD => adds.insert(capability),
+            Action::DROP => adds.remove(capability),
+        };
+        b.set(adds);
+    });
+}
+
+/// Fails with `Error::InvalidUpdate` for the first capability added to the
+/// bounding set of the libcap-ng state that the calling thread lost from its
+/// own, as applying can't bring it back.
+fn check_bounding_adds() -> Result<()> {
+    let adds = BOUNDING_ADDS.with(|b| b.get());
+    match adds.iter().find(|&cap| {
+        // Safe because this doesn't modify any local memory.
+        unsafe { libc::prctl(libc::PR_CAPBSET_READ, cap as libc::c_ulong, 0, 0, 0) == 0 }
+    }) {
+        Some(cap) => Err(Error::InvalidUpdate(cap)),
+        None => Ok(()),
+    }
+}
+
+fn set_loaded() {
+    LOADED.with(|l| l.set(true));
+}
+
+/// Fails unless the libcap-ng state of the calling thread was loaded or
+/// initialized, as libcap-ng answers queries about an uninitialized state
+/// as if it held no capabilities.
+fn check_loaded() -> Result<()> {
+    if LOADED.with(|l| l.get()) {
+        Ok(())
+    } else {
+        Err(Error::CapsNotLoaded)
+    }
+}
+
+/// Brings the libcap-ng state in line with the user namespace the calling
+/// thread is now in, for use right after entering one with `setns`.
+///
+/// Capabilities are relative to a user namespace, so the ones loaded before
+/// `setns` no longer apply. This must be called once `setns` has returned,
+/// on the same thread, and before any other capability query: it updates
+/// the root ID used when writing file capabilities to the ID that root in
+/// the new namespace maps to, unless that's the initial namespace, then
+/// targets the calling process and loads its capabilities again.
+///
+/// libcap-ng can't unset a root ID once set, so one set before entering the
+/// initial namespace is kept.
+pub fn refresh_after_setns() -> Result<()> {
+    if let Some(rootid) = support::namespace_rootid() {
+        if rootid != 0 && supports_ambient() && get_rootid()? != rootid {
+            set_rootid(rootid)?;
+        }
+    }
+
+    setpid(0);
+    get_caps_process()
+}
+
+/// Iterates over the IDs of all the capabilities libcap-ng knows about,
+/// leaving out any the running kernel doesn't support, when that can be
+/// told.
+pub(crate) fn known_capabilities() -> impl Iterator<Item = Capability> {
+    let last_cap = kernel_last_cap().unwrap_or(Capability::MAX);
+    // Safe because this doesn't modify any local memory, and we only check
+    // the returned pointer without dereferencing it.
+    (0..=last_cap).take_while(|&cap| unsafe { !bindings::capng_capability_to_name(cap).is_null() })
+}
+
+pub(crate) fn get_caps_raw_fd(fd: RawFd) -> Result<()> {
+    ffi_span!("get_caps_fd", fd);
+    bump_generation();
+    // Safe because this doesn't modify any local memory and doesn't alter
+    // the offset of the file descriptor.
+    let ret = unsafe { bindings::capng_get_caps_fd(fd) };
+
+    if ret == 0 {
+        set_loaded();
+        Ok(())
+    } else {
+        Err(Error::GetCapsFd(fd))
+    }
+}
+
+pub(crate) fn apply_caps_raw_fd(fd: RawFd) -> Result<()> {
+    ffi_span!("apply_caps_fd", fd);
+    // Safe because this doesn't modify any local memory and doesn't alter
+    // the offset of the file descriptor.
+    let ret = unsafe { bindings::capng_apply_caps_fd(fd) };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(Error::ApplyCapsFd(fd))
+    }
+}
+
+/// Runs `f` and then puts back the libcap-ng state that was in place before
+/// calling it, so helpers can load capabilities without clobbering whatever
+/// the caller was in the middle of configuring.
+pub(crate) fn with_saved_state<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let state = save_state().ok_or(Error::SaveState)?;
+    let ret = f();
+    restore_state(state);
+
+    ret
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::{BTreeSet, HashSet};
+
+    /// Returns whether the calling test should be skipped for lack of
+    /// privileges, telling so on the standard error.
+    pub(crate) fn skip_unprivileged() -> bool {
+        if running_privileged() {
+            return false;
+        }
+        eprintln!("skipped: requires CAP_SETPCAP, CAP_SETUID and CAP_SETGID");
+        true
+    }
+
+    /// Creates an empty file in the temporary directory, unique to the
+    /// calling test.
+    pub(crate) fn temp_file(name: &str) -> (PathBuf, File) {
+        let path = env::temp_dir().join(format!("capng-{}-{}", std::process::id(), name));
+        let file = File::create(&path).unwrap();
+        (path, file)
+    }
+
+    /// Serializes the tests that register process-wide hooks, like
+    /// `set_name_resolver`, with the ones whose results those hooks would
+    /// change, as tests run in parallel.
+    pub(crate) fn lock_hooks() -> std::sync::MutexGuard<'static, ()> {
+        static HOOKS: std::sync::Mutex<()> = std::sync::Mutex::new(());
+        // A failed test holding the lock doesn't leave a hook behind that
+        // matters to the others, so ignore poisoning.
+        HOOKS.lock().unwrap_or_else(|e| e.into_inner())
+    }
 
     #[test]
     fn basic_tests() {
@@ -475,4 +2069,1138 @@ mod tests {
             assert!(have_capability(Type::EFFECTIVE, c));
         }
     }
+
+    #[test]
+    fn capability_set_tests() {
+        let caps_text = ["KILL", "CHOWN", "SYS_ADMIN", "FOWNER", "KILL"];
+        let caps: BTreeSet<Capability> = caps_text
+            .iter()
+            .map(|c| name_to_capability(c).unwrap())
+            .collect();
+
+        let ids: Vec<Capability> = caps.iter().copied().collect();
+        assert_eq!(ids, vec![0, 3, 5, 21]);
+
+        let hashed: HashSet<Capability> = caps.iter().copied().collect();
+        assert_eq!(hashed.len(), caps.len());
+        assert!(hashed.contains(&name_to_capability("SYS_ADMIN").unwrap()));
+    }
+
+    #[test]
+    fn get_caps_process_error_tests() {
+        // No process can ever have this pid.
+        setpid(i32::MAX);
+        match get_caps_process() {
+            Err(Error::GetProcessCapabilities) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        setpid(0);
+        get_caps_process().unwrap();
+    }
+
+    #[test]
+    fn set_ambient_only_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        let cap = name_to_capability("NET_BIND_SERVICE").unwrap();
+        get_caps_process().unwrap();
+        if !have_capability(Type::PERMITTED, cap) {
+            return;
+        }
+
+        // Capabilities are per-thread, so this only affects the test thread.
+        set_ambient_only(&[cap]).unwrap();
+        get_caps_process().unwrap();
+        assert!(have_capability(Type::AMBIENT, cap));
+        assert!(have_capability(Type::INHERITABLE, cap));
+        assert!(have_capability(Type::PERMITTED, cap));
+        assert!(!have_capability(Type::EFFECTIVE, cap));
+        assert!(!have_capability(Type::PERMITTED, 0));
+    }
+
+    #[test]
+    fn parse_caps_text_tests() {
+        assert_eq!(parse_caps_text("chown, kill").unwrap(), vec![0, 5]);
+        assert_eq!(
+            parse_caps_text("CAP_NET_ADMIN,,sys_admin ").unwrap(),
+            vec![12, 21]
+        );
+        assert!(parse_caps_text("none").unwrap().is_empty());
+        assert!(parse_caps_text("").unwrap().is_empty());
+        match parse_caps_text("chown, bogus") {
+            Err(Error::GetCapabilityId(name)) => assert_eq!(name, "bogus"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        fill(Set::BOTH);
+        let text = print_caps_text(Print::BUFFER, Type::EFFECTIVE).unwrap();
+        assert_eq!(
+            parse_caps_text(&text).unwrap().len(),
+            text.split(", ").count()
+        );
+    }
+
+    #[test]
+    fn apply_from_env_tests() {
+        let var = "CAPNG_TEST_UNSET_VARIABLE";
+        env::remove_var(var);
+        match apply_from_env(var, Type::EFFECTIVE, Set::CAPS) {
+            Err(Error::ReadEnvironmentVariable(name)) => assert_eq!(name, var),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn const_bits_tests() {
+        const DESIRED: Type = Type::EFFECTIVE.union(Type::PERMITTED);
+        const DESIRED_BITS: u32 = DESIRED.bits_of();
+        const SELECT: Set = match Set::from_bits(Set::BOTH.bits_of()) {
+            Some(set) => set,
+            None => Set::empty(),
+        };
+        const FLAGS: u32 = Flags::DROP_SUPP_GRP.union(Flags::CLEAR_BOUNDING).bits_of();
+
+        assert_eq!(DESIRED_BITS, 3);
+        assert_eq!(SELECT, Set::CAPS | Set::BOUNDS);
+        assert_eq!(FLAGS, 3);
+
+        match Type::from_bits_truncate(3) {
+            DESIRED => (),
+            t => panic!("unexpected type: {:?}", t),
+        }
+    }
+
+    #[test]
+    fn bounding_set_tests() {
+        clear(Set::BOTH);
+        updatev(Action::ADD, Type::BOUNDING_SET, vec!["FOWNER", "KILL"]).unwrap();
+        assert_eq!(bounding_set(), vec![3, 5]);
+        assert!(capabilities_in(Type::EFFECTIVE).is_empty());
+
+        // Capabilities are per-thread, so compare against this thread's view.
+        get_caps_process().unwrap();
+        let status = std::fs::read_to_string("/proc/thread-self/status").unwrap();
+        let mask = status
+            .lines()
+            .find_map(|l| l.strip_prefix("CapBnd:"))
+            .map(|m| u64::from_str_radix(m.trim(), 16).unwrap())
+            .unwrap();
+        let expected: Vec<Capability> = (0..64).filter(|c| mask & (1 << c) != 0).collect();
+        assert_eq!(bounding_set(), expected);
+    }
+
+    #[test]
+    fn require_capabilities_tests() {
+        require_capabilities(&[]).unwrap();
+
+        let cap = name_to_capability("SYS_ADMIN").unwrap();
+        get_caps_process().unwrap();
+        if have_capability(Type::EFFECTIVE, cap) {
+            require_capabilities(&[cap]).unwrap();
+            update(vec![CUpdate {
+                action: Action::DROP,
+                cap_type: Type::EFFECTIVE,
+                capability: cap,
+            }])
+            .unwrap();
+            apply(Set::CAPS).unwrap();
+        }
+
+        match require_capabilities(&[cap]) {
+            Err(Error::MissingCapability(c)) => assert_eq!(c, cap),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn grantable_file_caps_tests() {
+        let (path, file) = temp_file("grantable");
+        let caps = [0, name_to_capability("SYS_RESOURCE").unwrap()];
+
+        clear(Set::CAPS);
+        for &cap in &caps {
+            update(vec![CUpdate {
+                action: Action::ADD,
+                cap_type: Type::PERMITTED,
+                capability: cap,
+            }])
+            .unwrap();
+        }
+        if apply_caps_fd(file.try_clone().unwrap()).is_ok() {
+            get_caps_process().unwrap();
+            let expected: Vec<Capability> = caps
+                .iter()
+                .copied()
+                .filter(|&cap| have_capability(Type::PERMITTED, cap))
+                .collect();
+
+            clear(Set::BOTH);
+            assert_eq!(grantable_file_caps(&file).unwrap(), expected);
+            assert_eq!(have_capabilities(Set::BOTH).unwrap(), HaveCapsResult::NONE);
+        }
+
+        std::fs::remove_file(path).unwrap();
+        let file = File::open("/dev/null").unwrap();
+        assert!(grantable_file_caps(&file).is_err());
+    }
+
+    #[test]
+    fn error_display_tests() {
+        assert_eq!(
+            Error::UpdateCapability(12).to_string(),
+            "failed to update the status of capability CAP_NET_ADMIN (12)"
+        );
+        assert_eq!(
+            Error::MissingCapability(6).to_string(),
+            "missing required capability CAP_SETGID (6)"
+        );
+        assert_eq!(
+            Error::NameToCapability(1000).to_string(),
+            "failed to find the name for capability 1000"
+        );
+    }
+
+    #[test]
+    fn self_check_tests() {
+        std::thread::spawn(|| {
+            get_caps_process().unwrap();
+            let caps = ProcessCaps::from_state();
+
+            self_check().unwrap();
+            assert_eq!(ProcessCaps::from_state(), caps);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn state_matches_tests() {
+        clear(Set::BOTH);
+        updatev(Action::ADD, Type::PERMITTED, vec!["CHOWN", "KILL"]).unwrap();
+        updatev(Action::ADD, Type::BOUNDING_SET, vec!["KILL"]).unwrap();
+        let state = save_state().unwrap();
+
+        let mut caps = ProcessCaps::default();
+        caps.permitted.extend(vec![0, 5]);
+        caps.bounding.insert(5);
+
+        clear(Set::BOTH);
+        assert!(state_matches(&state, &caps));
+        assert_eq!(have_capabilities(Set::BOTH).unwrap(), HaveCapsResult::NONE);
+
+        caps.effective.insert(0);
+        assert!(!state_matches(&state, &caps));
+
+        restore_state(state);
+        assert!(have_capability(Type::PERMITTED, 5));
+        assert!(have_capability(Type::BOUNDING_SET, 5));
+    }
+
+    #[test]
+    fn state_matches_cached_tests() {
+        // The cache is per thread, so keep it from leaking into other tests.
+        std::thread::spawn(|| {
+            clear(Set::BOTH);
+            updatev(Action::ADD, Type::PERMITTED, vec!["CHOWN"]).unwrap();
+            let state = save_state().unwrap();
+
+            let mut caps = ProcessCaps::default();
+            caps.permitted.insert(0);
+
+            // Cache the cleared state, which state_matches must not answer
+            // from once it restored the saved one.
+            clear(Set::BOTH);
+            enable_caps_cache().unwrap();
+            clear(Set::BOTH);
+            assert!(!have_capability(Type::PERMITTED, 0));
+            assert!(state_matches(&state, &caps));
+            assert!(!have_capability(Type::PERMITTED, 0));
+            disable_caps_cache();
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn clear_ambient_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        let cap = name_to_capability("NET_BIND_SERVICE").unwrap();
+        get_caps_process().unwrap();
+        if !have_capability(Type::PERMITTED, cap) {
+            return;
+        }
+
+        // Capabilities are per-thread, so this only affects the test thread.
+        set_ambient_only(&[cap]).unwrap();
+        clear_ambient().unwrap();
+        get_caps_process().unwrap();
+        assert!(!have_capability(Type::AMBIENT, cap));
+        assert!(have_capability(Type::INHERITABLE, cap));
+        assert!(have_capability(Type::PERMITTED, cap));
+    }
+
+    #[test]
+    fn type_iter_tests() {
+        let types: Vec<Type> = (Type::EFFECTIVE | Type::PERMITTED).into_iter().collect();
+        assert_eq!(types, vec![Type::EFFECTIVE, Type::PERMITTED]);
+
+        let types: Vec<Type> = Type::all().into_iter().collect();
+        assert_eq!(
+            types,
+            vec![
+                Type::EFFECTIVE,
+                Type::PERMITTED,
+                Type::INHERITABLE,
+                Type::BOUNDING_SET,
+                Type::AMBIENT
+            ]
+        );
+        assert_eq!(Type::empty().into_iter().next(), None);
+    }
+
+    #[test]
+    fn print_caps_text_sorted_tests() {
+        clear(Set::BOTH);
+        assert_eq!(print_caps_text_sorted(Type::EFFECTIVE).unwrap(), "");
+
+        updatev(
+            Action::ADD,
+            Type::EFFECTIVE,
+            vec!["NET_ADMIN", "KILL", "CHOWN"],
+        )
+        .unwrap();
+        let text = print_caps_text_sorted(Type::EFFECTIVE).unwrap();
+        assert_eq!(text, "chown, kill, net_admin");
+        assert_eq!(Some(text), print_caps_text(Print::BUFFER, Type::EFFECTIVE));
+
+        assert!(print_caps_text_sorted(Type::EFFECTIVE | Type::PERMITTED).is_none());
+    }
+
+    #[test]
+    fn caps_not_loaded_tests() {
+        // A new thread starts with uninitialized libcap-ng state.
+        std::thread::spawn(|| {
+            match have_capabilities(Set::CAPS) {
+                Err(Error::CapsNotLoaded) => (),
+                r => panic!("unexpected result: {:?}", r),
+            }
+            assert!(have_permitted_capabilities().is_err());
+            assert!(try_have_capability(Type::EFFECTIVE, 0).is_err());
+
+            // Restoring a state saved before loading doesn't count.
+            let state = save_state().unwrap();
+            fill(Set::BOTH);
+            try_have_capability(Type::EFFECTIVE, 0).unwrap();
+            restore_state(state);
+            assert!(have_capabilities(Set::CAPS).is_err());
+
+            clear(Set::BOTH);
+            assert!(!try_have_capability(Type::EFFECTIVE, 0).unwrap());
+            assert!(!try_have_capability(Type::EFFECTIVE, "chown").unwrap());
+            assert!(try_have_capability(Type::EFFECTIVE, "bogus").is_err());
+            assert_eq!(have_capabilities(Set::CAPS).unwrap(), HaveCapsResult::NONE);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn keep_only_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        std::thread::spawn(|| {
+            get_caps_process().unwrap();
+            assert!(keep_only(&["chown", "bogus"]).is_err());
+            assert!(have_capability(Type::EFFECTIVE, cap::SYS_ADMIN));
+
+            keep_only(&["CHOWN", "kill"]).unwrap();
+            get_caps_process().unwrap();
+            assert_eq!(capabilities_in(Type::EFFECTIVE), [0, 5]);
+            assert_eq!(capabilities_in(Type::BOUNDING_SET), [0, 5]);
+
+            keep_only(&[5]).unwrap();
+            get_caps_process().unwrap();
+            assert_eq!(capabilities_in(Type::PERMITTED), [5]);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn have_caps_result_tests() {
+        assert!(HaveCapsResult::FULL.is_full());
+        assert!(!HaveCapsResult::PARTIAL.is_full());
+        assert!(HaveCapsResult::PARTIAL.is_partial());
+        assert!(HaveCapsResult::NONE.is_none());
+        assert!(!HaveCapsResult::FAIL.is_none());
+
+        assert!(HaveCapsResult::FULL.at_least(HaveCapsResult::PARTIAL));
+        assert!(HaveCapsResult::PARTIAL.at_least(HaveCapsResult::PARTIAL));
+        assert!(!HaveCapsResult::NONE.at_least(HaveCapsResult::PARTIAL));
+        assert!(!HaveCapsResult::FAIL.at_least(HaveCapsResult::NONE));
+    }
+
+    #[test]
+    fn require_full_tests() {
+        std::thread::spawn(|| {
+            match require_full(Set::CAPS) {
+                Err(Error::CapsNotLoaded) => (),
+                r => panic!("unexpected result: {:?}", r),
+            }
+
+            fill(Set::CAPS);
+            require_full(Set::CAPS).unwrap();
+
+            clear(Set::CAPS);
+            match require_full(Set::CAPS) {
+                Err(Error::InsufficientCapabilities(HaveCapsResult::NONE)) => (),
+                r => panic!("unexpected result: {:?}", r),
+            }
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn set_capability_tests() {
+        std::thread::spawn(|| {
+            clear(Set::BOTH);
+            assert!(!set_capability(Type::PERMITTED, cap::KILL, true).unwrap());
+            assert!(set_capability(Type::PERMITTED, cap::KILL, true).unwrap());
+            assert_eq!(capabilities_in(Type::PERMITTED), [cap::KILL]);
+            assert!(capabilities_in(Type::EFFECTIVE).is_empty());
+
+            assert!(set_capability(Type::PERMITTED, cap::KILL, false).unwrap());
+            assert!(!set_capability(Type::PERMITTED, cap::KILL, false).unwrap());
+            assert!(capabilities_in(Type::PERMITTED).is_empty());
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn apply_atomic_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        std::thread::spawn(|| {
+            get_caps_process().unwrap();
+            let before = ProcessCaps::from_state();
+            assert!(before.effective.contains(cap::CHOWN));
+
+            // Dropping CAP_CHOWN succeeds, but CAP_KILL can't be ambient
+            // without being inheritable too.
+            update(vec![
+                CUpdate {
+                    action: Action::DROP,
+                    cap_type: Type::EFFECTIVE,
+                    capability: cap::CHOWN,
+                },
+                CUpdate {
+                    action: Action::ADD,
+                    cap_type: Type::AMBIENT,
+                    capability: cap::KILL,
+                },
+            ])
+            .unwrap();
+            assert!(apply_atomic(Set::CAPS | Set::AMBIENT).is_err());
+
+            // The state is left as configured, but the process is as before.
+            assert!(!have_capability(Type::EFFECTIVE, cap::CHOWN));
+            get_caps_process().unwrap();
+            assert_eq!(ProcessCaps::from_state(), before);
+
+            update(vec![CUpdate {
+                action: Action::DROP,
+                cap_type: Type::EFFECTIVE,
+                capability: cap::CHOWN,
+            }])
+            .unwrap();
+            apply_atomic(Set::CAPS).unwrap();
+            get_caps_process().unwrap();
+            assert!(!have_capability(Type::EFFECTIVE, cap::CHOWN));
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn no_new_privs_tests() {
+        let status = std::fs::read_to_string("/proc/thread-self/status").unwrap();
+        let expected = status
+            .lines()
+            .find_map(|l| l.strip_prefix("NoNewPrivs:"))
+            .map(|v| v.trim() == "1")
+            .unwrap();
+
+        assert_eq!(no_new_privs().unwrap(), expected);
+    }
+
+    #[test]
+    fn forbid_forever_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        // The bounding set and no_new_privs are per thread, so change them on
+        // a thread of our own.
+        std::thread::spawn(|| {
+            let caps = [cap!(SYS_BOOT), cap!(SYS_MODULE)];
+            forbid_forever(&caps).unwrap();
+            assert!(no_new_privs().unwrap());
+            get_caps_process().unwrap();
+            assert!(caps
+                .iter()
+                .all(|&cap| !have_capability(Type::BOUNDING_SET, cap)));
+
+            // Dropping them again is a no-op.
+            forbid_forever(&caps).unwrap();
+
+            // The flag is still set, and told about, when dropping fails,
+            // here as the capabilities of a missing process can't be loaded.
+            setpid(Pid::MAX);
+            match forbid_forever(&[cap!(SYS_RAWIO)]) {
+                Err(Error::ForbidForever {
+                    bounding: Some(_),
+                    no_new_privs: true,
+                }) => (),
+                r => panic!("unexpected result: {:?}", r),
+            }
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn print_caps_text_empty_tests() {
+        // A new thread starts with uninitialized libcap-ng state.
+        std::thread::spawn(|| {
+            assert_eq!(print_caps_text(Print::BUFFER, Type::EFFECTIVE), None);
+
+            clear(Set::BOTH);
+            assert_eq!(
+                print_caps_text(Print::BUFFER, Type::EFFECTIVE),
+                Some(String::new())
+            );
+            assert_eq!(print_caps_text(Print::STDOUT, Type::EFFECTIVE), None);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn validate_ambient_preconditions_tests() {
+        clear(Set::BOTH | Set::AMBIENT);
+        updatev(
+            Action::ADD,
+            Type::PERMITTED | Type::INHERITABLE | Type::AMBIENT,
+            vec!["CHOWN"],
+        )
+        .unwrap();
+        updatev(Action::ADD, Type::PERMITTED | Type::AMBIENT, vec!["KILL"]).unwrap();
+
+        validate_ambient_preconditions(&[0]).unwrap();
+        match validate_ambient_preconditions(&[0, 5]) {
+            Err(Error::AmbientPreconditionUnmet(cap)) => assert_eq!(cap, 5),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn with_pid_tests() {
+        {
+            let _scope = with_pid(i32::MAX);
+            assert!(get_caps_process().is_err());
+        }
+        get_caps_process().unwrap();
+    }
+
+    #[test]
+    fn invalid_bounding_update_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        // The bounding set is per thread, so change it on a thread of our own.
+        std::thread::spawn(|| {
+            let (boot, kill) = (cap!(SYS_BOOT), cap!(KILL));
+            get_caps_process().unwrap();
+            update(vec![CUpdate {
+                action: Action::DROP,
+                cap_type: Type::BOUNDING_SET,
+                capability: boot,
+            }])
+            .unwrap();
+            apply(Set::BOUNDS).unwrap();
+
+            // Keeping capabilities still in the bounding set is fine.
+            clear(Set::BOUNDS);
+            updatev(Action::ADD, Type::BOUNDING_SET, vec!["kill"]).unwrap();
+            apply(Set::BOUNDS).unwrap();
+            get_caps_process().unwrap();
+            assert!(have_capability(Type::BOUNDING_SET, kill));
+            assert!(!have_capability(Type::BOUNDING_SET, cap!(CHOWN)));
+
+            update(vec![CUpdate {
+                action: Action::ADD,
+                cap_type: Type::BOUNDING_SET,
+                capability: boot,
+            }])
+            .unwrap();
+            let state = save_state().unwrap();
+            match apply(Set::BOUNDS) {
+                Err(Error::InvalidUpdate(cap)) => assert_eq!(cap, boot),
+                r => panic!("unexpected result: {:?}", r),
+            }
+            restore_state(state);
+            assert!(apply(Set::BOUNDS).is_err());
+
+            update(vec![CUpdate {
+                action: Action::DROP,
+                cap_type: Type::BOUNDING_SET,
+                capability: boot,
+            }])
+            .unwrap();
+            apply(Set::BOUNDS).unwrap();
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn latent_capabilities_tests() {
+        // Capabilities are per thread, so change them on a thread of our own.
+        std::thread::spawn(|| {
+            get_caps_process().unwrap();
+            let caps = ProcessCaps::from_state();
+            let expected: Vec<Capability> =
+                caps.permitted.difference(caps.effective).iter().collect();
+            assert_eq!(latent_capabilities().unwrap(), expected);
+
+            if !caps.effective.contains(cap!(KILL)) {
+                return;
+            }
+            update(vec![CUpdate {
+                action: Action::DROP,
+                cap_type: Type::EFFECTIVE,
+                capability: cap!(KILL),
+            }])
+            .unwrap();
+            apply(Set::CAPS).unwrap();
+            assert!(latent_capabilities().unwrap().contains(&cap!(KILL)));
+            assert!(!have_capability(Type::EFFECTIVE, cap!(KILL)));
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn apply_detailed_tests() {
+        // Capabilities are per thread, so change them on a thread of our own.
+        std::thread::spawn(|| {
+            get_caps_process().unwrap();
+            let permitted = ProcessCaps::from_state().permitted;
+            let report = apply_detailed(Set::BOTH);
+            assert!(report.is_ok());
+            assert!(matches!(report.caps, Some(Ok(()))));
+            assert!(matches!(report.bounds, Some(Ok(()))));
+            assert!(report.ambient.is_none());
+
+            if permitted.len() == known_capabilities().count() {
+                return;
+            }
+            // Raising every capability fails, as some aren't permitted,
+            // while the unchanged bounding set applies fine.
+            fill(Set::CAPS);
+            let report = apply_detailed(Set::BOTH);
+            assert!(!report.is_ok());
+            assert!(matches!(report.caps, Some(Err(Error::ApplyCapabilities))));
+            assert!(matches!(report.bounds, Some(Ok(()))));
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn capability_id_bounds_tests() {
+        std::thread::spawn(|| {
+            fill(Set::BOTH);
+            let last = known_capabilities().last().unwrap();
+            for t in Type::all() {
+                // libcap-ng doesn't fill the inheritable set.
+                if t != Type::INHERITABLE && t != Type::AMBIENT {
+                    assert!(have_capability(t, last));
+                }
+                for &cap in &[last + 1, 63, 64, i32::MAX as Capability, Capability::MAX] {
+                    assert!(!have_capability(t, cap), "{:?} {}", t, cap);
+                }
+            }
+
+            enable_caps_cache().unwrap();
+            fill(Set::BOTH);
+            assert!(have_capability(Type::EFFECTIVE, last));
+            assert!(!have_capability(Type::EFFECTIVE, 64));
+            assert!(!have_capability(Type::EFFECTIVE, Capability::MAX));
+            disable_caps_cache();
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn with_target_pid_tests() {
+        let pid = std::process::id() as Pid;
+        let caps = with_target_pid(pid, CapState::capture).unwrap();
+        assert_eq!(caps, CapState::capture().unwrap());
+
+        assert!(with_target_pid(i32::MAX, get_caps_process).is_err());
+        get_caps_process().unwrap();
+
+        let panicked = std::panic::catch_unwind(|| {
+            with_target_pid(i32::MAX, || -> Result<()> { panic!("inspecting") })
+        });
+        assert!(panicked.is_err());
+        get_caps_process().unwrap();
+    }
+
+    #[test]
+    fn drop_to_user_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        // Changing IDs affects every thread, so do it in a child process.
+        // Safe because the child only sticks to libcap-ng and reading a file
+        // before exiting.
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            let ok = drop_to_user(65534, 65534, &[10], Flags::DROP_SUPP_GRP).is_ok()
+                && get_caps_process().is_ok()
+                && capabilities_in(Type::AMBIENT) == [10]
+                && capabilities_in(Type::PERMITTED) == [10]
+                && unsafe { libc::getuid() } == 65534;
+            // Safe because we exit without running anything else.
+            unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+        }
+
+        let mut status = 0;
+        // Safe because status is a valid pointer.
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+
+    #[test]
+    fn apply_all_threads_tests() {
+        // The test harness runs at least a main thread and this one.
+        match apply_all_threads(Set::CAPS) {
+            Err(Error::MultipleThreads(count)) => assert!(count > 1),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        // A forked child only runs the thread that forked.
+        // Safe because the child only sticks to libcap-ng and reading a
+        // directory before exiting.
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            let ok = warn_if_multithreaded().is_ok()
+                && get_caps_process().is_ok()
+                && apply_all_threads(Set::CAPS).is_ok();
+            // Safe because we exit without running anything else.
+            unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+        }
+
+        let mut status = 0;
+        // Safe because status is a valid pointer.
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+
+    #[test]
+    fn can_raise_tests() {
+        let status = std::fs::read_to_string("/proc/thread-self/status").unwrap();
+        let prm = status
+            .lines()
+            .find_map(|l| l.strip_prefix("CapPrm:"))
+            .map(|v| u64::from_str_radix(v.trim(), 16).unwrap())
+            .unwrap();
+
+        for cap in known_capabilities() {
+            assert_eq!(can_raise(cap), prm & (1 << cap) != 0, "cap {}", cap);
+        }
+    }
+
+    #[test]
+    fn apply_all_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        assert_eq!(Set::ALL, Set::CAPS | Set::BOUNDS | Set::AMBIENT);
+
+        get_caps_process().unwrap();
+        apply(Set::ALL).unwrap();
+    }
+
+    #[test]
+    fn cupdate_display_tests() {
+        let update = CUpdate {
+            action: Action::ADD,
+            cap_type: Type::EFFECTIVE,
+            capability: 0,
+        };
+        assert_eq!(update.to_string(), "+EFFECTIVE CAP_CHOWN");
+        assert_eq!(update, update.clone());
+
+        let update = CUpdate {
+            action: Action::DROP,
+            cap_type: Type::PERMITTED | Type::AMBIENT,
+            capability: 1000,
+        };
+        assert_eq!(update.to_string(), "-PERMITTED|AMBIENT 1000");
+        assert!(format!("{:?}", update).contains("DROP"));
+    }
+
+    #[test]
+    fn lock_bits_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        // Securebits are per thread, so keep them away from other tests.
+        let bits = std::thread::spawn(|| {
+            lock_bits(Securebits::NOROOT).unwrap();
+            // Safe because this doesn't modify any local memory.
+            unsafe { libc::prctl(libc::PR_GET_SECUREBITS) }
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(
+            Securebits::from_bits_truncate(bits as u32),
+            Securebits::NOROOT | Securebits::NOROOT_LOCKED
+        );
+    }
+
+    #[test]
+    fn eprint_caps_text_tests() {
+        clear(Set::CAPS);
+        eprint_caps_text(Type::EFFECTIVE);
+        eprint_caps_text(Type::EFFECTIVE | Type::PERMITTED);
+    }
+
+    #[test]
+    fn caps_equal_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        get_caps_process().unwrap();
+        let mut permitted = capabilities_in(Type::PERMITTED);
+        permitted.reverse();
+        assert!(caps_equal(Type::PERMITTED, &permitted).unwrap());
+        assert!(caps_equal(Type::AMBIENT | Type::INHERITABLE, &[]).unwrap());
+
+        let extra = permitted.pop().unwrap();
+        assert!(!caps_equal(Type::PERMITTED, &permitted).unwrap());
+        permitted.extend(&[extra, extra, 1000]);
+        assert!(!caps_equal(Type::PERMITTED, &permitted).unwrap());
+    }
+
+    #[test]
+    fn reset_after_fork_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        setpid(i32::MAX);
+        clear(Set::BOTH);
+        reset_after_fork().unwrap();
+        let caps = ProcessCaps::from_state();
+
+        get_caps_process().unwrap();
+        assert_eq!(caps, ProcessCaps::from_state());
+        assert!(!caps.permitted.is_empty());
+    }
+
+    #[test]
+    fn cap_macro_tests() {
+        assert_eq!(cap!(CHOWN), 0);
+        assert_eq!(cap!(NET_ADMIN), name_to_capability("net_admin").unwrap());
+        assert_eq!(
+            cap!(CHECKPOINT_RESTORE),
+            name_to_capability("checkpoint_restore").unwrap()
+        );
+    }
+
+    #[test]
+    fn caps_generation_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        get_caps_process().unwrap();
+        let caps = ProcessCaps::from_state();
+        assert!(!caps.is_stale());
+        assert!(!ProcessCaps::from_state().is_stale());
+        assert!(have_capability(Type::PERMITTED, 0));
+        assert!(!caps.is_stale());
+
+        let generation = caps_generation();
+        clear(Set::CAPS);
+        assert!(caps.is_stale());
+        assert_ne!(caps_generation(), generation);
+
+        // Staleness doesn't affect comparisons.
+        get_caps_process().unwrap();
+        assert_eq!(ProcessCaps::from_state(), caps);
+    }
+
+    #[test]
+    fn enum_try_from_tests() {
+        assert_eq!(Action::try_from(Action::ADD as u32).unwrap(), Action::ADD);
+        assert_eq!(Action::try_from(0).unwrap(), Action::DROP);
+        match Action::try_from(2) {
+            Err(Error::InvalidAction(n)) => assert_eq!(n, 2),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        assert_eq!(
+            Print::try_from(Print::BUFFER as u32).unwrap(),
+            Print::BUFFER
+        );
+        assert_eq!(Print::try_from(0).unwrap(), Print::STDOUT);
+        match Print::try_from(7) {
+            Err(Error::InvalidPrint(n)) => assert_eq!(n, 7),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn drop_if_privileged_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        // Keep the process credentials intact by dropping in a child process.
+        // Safe because the child only sticks to libcap-ng and a few syscalls
+        // before exiting.
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            let (setgid, setuid) = (cap!(SETGID), cap!(SETUID));
+            let ok = drop_if_privileged(&[setgid, setuid]).unwrap_or(false)
+                && get_caps_process().is_ok()
+                && capabilities_in(Type::EFFECTIVE) == [setgid, setuid]
+                && capabilities_in(Type::BOUNDING_SET) == [setgid, setuid]
+                // Without KEEP_CAPS, leaving root drops every capability.
+                && unsafe { libc::setgid(65534) == 0 && libc::setuid(65534) == 0 }
+                && drop_if_privileged(&[]).ok() == Some(false);
+            // Safe because we exit without running anything else.
+            unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+        }
+
+        let mut status = 0;
+        // Safe because status is a valid pointer.
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+
+    #[test]
+    fn capability_transitions_locked_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        // Securebits are per thread, so keep them away from other tests.
+        std::thread::spawn(|| {
+            assert!(!capability_transitions_locked().unwrap());
+            lock_bits(Securebits::KEEP_CAPS).unwrap();
+            assert!(!capability_transitions_locked().unwrap());
+            lock_bits(Securebits::NOROOT).unwrap();
+            assert!(capability_transitions_locked().unwrap());
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn change_id_retry_tests() {
+        let run = |attempts, failure: ChangeIdFailure, fails: u32| {
+            let mut count = 0;
+            let ret = retry_change_id(attempts, || {
+                count += 1;
+                if count <= fails {
+                    Err(failure)
+                } else {
+                    Ok(())
+                }
+            });
+            (ret.is_ok(), count)
+        };
+        let eagain = (CHANGE_ID_SUPP_GRP_FAILED, Some(libc::EAGAIN));
+
+        // Unknown users and failures of any other step aren't retried, even
+        // if errno happens to look transient.
+        assert_eq!(run(5, (-10, Some(libc::EAGAIN)), 5), (false, 1));
+        assert_eq!(run(5, (-6, Some(libc::EAGAIN)), 5), (false, 1));
+        assert_eq!(
+            run(5, (CHANGE_ID_SUPP_GRP_FAILED, Some(libc::EPERM)), 5),
+            (false, 1)
+        );
+        assert_eq!(run(5, (CHANGE_ID_SUPP_GRP_FAILED, None), 5), (false, 1));
+
+        // Transient failures are retried until success, or `attempts` ran.
+        assert_eq!(run(5, eagain, 0), (true, 1));
+        assert_eq!(run(5, eagain, 2), (true, 3));
+        assert_eq!(run(3, eagain, 5), (false, 3));
+        assert_eq!(run(0, eagain, 5), (false, 1));
+    }
+
+    #[test]
+    fn drop_bounding_once_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        // The bounding set is per thread, so keep the drops away from other
+        // tests.
+        std::thread::spawn(|| {
+            let (pacct, resource) = (cap!(SYS_PACCT), cap!(SYS_RESOURCE));
+            assert!(!bounding_set().contains(&resource));
+
+            assert_eq!(
+                drop_bounding_once(&[pacct, resource, pacct]).unwrap(),
+                vec![pacct]
+            );
+            assert!(!bounding_set().contains(&pacct));
+            assert!(drop_bounding_once(&[pacct]).unwrap().is_empty());
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn running_privileged_tests() {
+        let status = std::fs::read_to_string("/proc/thread-self/status").unwrap();
+        let eff = status
+            .lines()
+            .find_map(|l| l.strip_prefix("CapEff:"))
+            .map(|v| u64::from_str_radix(v.trim(), 16).unwrap())
+            .unwrap();
+
+        let expected = PRIVILEGED_CAPS.iter().all(|&cap| eff & (1 << cap) != 0);
+        assert_eq!(running_privileged(), expected);
+    }
+
+    #[test]
+    fn adopt_caps_from_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        // Capabilities are per thread, so lower them on a thread of our own,
+        // and then take them back from the main thread.
+        std::thread::spawn(|| {
+            let cap = cap!(SYS_ADMIN);
+            get_caps_process().unwrap();
+            update(vec![CUpdate {
+                action: Action::DROP,
+                cap_type: Type::EFFECTIVE,
+                capability: cap,
+            }])
+            .unwrap();
+            apply(Set::CAPS).unwrap();
+            assert!(require_capabilities(&[cap]).is_err());
+
+            adopt_caps_from(std::process::id() as Pid, Set::CAPS).unwrap();
+            require_capabilities(&[cap]).unwrap();
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn apply_raw_tests() {
+        get_caps_process().unwrap();
+        // Safe because these are valid select bits, which re-apply the
+        // current capabilities.
+        unsafe { apply_raw(Set::CAPS.bits()) }.unwrap();
+    }
+
+    #[test]
+    fn capabilities_from_names_tests() {
+        assert_eq!(
+            capabilities_from_names(&["NET_ADMIN", "chown"]).unwrap(),
+            vec![12, 0]
+        );
+        match capabilities_from_names(&["chown", "bogus", "other"]) {
+            Err(Error::GetCapabilityId(name)) => assert_eq!(name, "bogus"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        assert_eq!(
+            capabilities_from_names_lenient(&["kill", "bogus", "chown"]),
+            vec![5, 0]
+        );
+    }
+
+    #[test]
+    fn dry_run_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        // Dry-run mode affects every thread, so turn it on in a child
+        // process. Safe because the child only sticks to libcap-ng and
+        // reading a file before exiting.
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            set_dry_run(true);
+            clear(Set::BOTH);
+            let ok = apply(Set::BOTH).is_ok()
+                && change_id(65534, 65534, Flags::empty()).is_ok()
+                && lock().is_ok()
+                && lock_bits(Securebits::NOROOT).is_ok()
+                && get_caps_process().is_ok()
+                && running_privileged()
+                && !capability_transitions_locked().unwrap_or(true)
+                // Safe because this doesn't modify any local memory.
+                && unsafe { libc::getuid() } == 0;
+            // Safe because we exit without running anything else.
+            unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+        }
+
+        let mut status = 0;
+        // Safe because status is a valid pointer.
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+
+    #[test]
+    fn refresh_after_setns_tests() {
+        // The root ID is per thread, so keep it away from other tests.
+        std::thread::spawn(|| {
+            clear(Set::BOTH);
+            refresh_after_setns().unwrap();
+            assert_eq!(get_rootid().unwrap(), UNSET_ROOTID);
+            assert_eq!(capabilities_in(Type::BOUNDING_SET), bounding_set());
+        })
+        .join()
+        .unwrap();
+    }
 }
@@ -0,0 +1,271 @@
+// Copyright (C) 2020 Red Hat, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+//! Lookups between capability names and IDs.
+//!
+//! Unlike the rest of the crate, nothing in here reads or modifies the
+//! libcap-ng state: names come from tables libcap-ng and the crate never
+//! change, and from the resolver optionally set with `set_name_resolver`,
+//! which is behind a lock. These functions are therefore safe to call from
+//! any number of threads at once, and don't need to be serialized with the
+//! functions that configure or apply capabilities.
+
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::sync::RwLock;
+
+use crate::{bindings, Capability, Error, Result};
+
+pub use crate::info::{capability_info, CapabilityInfo};
+
+type NameResolver = Box<dyn Fn(&str) -> Option<Capability> + Send + Sync>;
+
+// The perfect hash map from the lowercase names of the crate's capability
+// table to their IDs, generated by the build script.
+include!(concat!(env!("OUT_DIR"), "/names_table.rs"));
+
+/// Room for the longest name in `NAME_TABLE`, so names can be lowercased on
+/// the stack before looking them up.
+const MAX_NAME_LEN: usize = 32;
+
+/// The resolver set with `set_name_resolver`, if any.
+static RESOLVER: RwLock<Option<NameResolver>> = RwLock::new(None);
+
+/// Registers `f` to resolve capability names before libcap-ng does, for the
+/// whole process, e.g. to accept aliases like `"network-admin"` for
+/// `CAP_NET_ADMIN`. Names `f` returns `None` for are looked up as usual.
+///
+/// `f` is given names lowercased, as they're matched case-insensitively.
+/// This replaces any resolver set before. It's consulted by
+/// `name_to_capability` and `lookup_name_fast`, and so by everything built
+/// on them, like `updatev`.
+pub fn set_name_resolver(f: impl Fn(&str) -> Option<Capability> + Send + Sync + 'static) {
+    *RESOLVER.write().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(f));
+}
+
+/// Unregisters the resolver set with `set_name_resolver`, if any.
+pub fn clear_name_resolver() {
+    *RESOLVER.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// A capability given either by ID, like `12`, or by name, like
+/// `"net_admin"`, so helpers such as `keep_only` accept both.
+///
+/// Names are resolved with `name_to_capability`, whose error is returned
+/// for unknown ones. IDs are taken as is, apart from negative ones, which
+/// fail with `Error::GetCapabilityId`.
+pub trait ToCapability {
+    fn to_capability(&self) -> Result<Capability>;
+}
+
+impl ToCapability for Capability {
+    fn to_capability(&self) -> Result<Capability> {
+        Ok(*self)
+    }
+}
+
+impl ToCapability for i32 {
+    fn to_capability(&self) -> Result<Capability> {
+        Capability::try_from(*self).map_err(|_| Error::GetCapabilityId(self.to_string()))
+    }
+}
+
+impl ToCapability for str {
+    fn to_capability(&self) -> Result<Capability> {
+        name_to_capability(self)
+    }
+}
+
+impl ToCapability for String {
+    fn to_capability(&self) -> Result<Capability> {
+        name_to_capability(self)
+    }
+}
+
+impl<T: ToCapability + ?Sized> ToCapability for &T {
+    fn to_capability(&self) -> Result<Capability> {
+        (**self).to_capability()
+    }
+}
+
+/// Resolves a capability name, like `"net_admin"`, to its ID, asking the
+/// resolver set with `set_name_resolver` first.
+pub fn name_to_capability(name: &str) -> Result<Capability> {
+    match resolve(&name.to_ascii_lowercase()) {
+        Some(cap) => Ok(cap),
+        None => libcapng_name_to_capability(name),
+    }
+}
+
+/// Asks the resolver set with `set_name_resolver`, if any, about `lower`,
+/// which must be lowercase.
+fn resolve(lower: &str) -> Option<Capability> {
+    let resolver = RESOLVER.read().unwrap_or_else(|e| e.into_inner());
+    resolver.as_ref().and_then(|f| f(lower))
+}
+
+/// Resolves a capability name through libcap-ng alone.
+fn libcapng_name_to_capability(name: &str) -> Result<Capability> {
+    let cstr = CString::new(name).map_err(|_| Error::ConvertCapabilityName)?;
+
+    // Safe because this doesn't modify any local memory and we have converted
+    // name to a CString.
+    let cap_id: i32 = unsafe { bindings::capng_name_to_capability(cstr.as_ptr() as *const c_char) };
+
+    // Any negative value means failure, and every other one is a valid ID.
+    Capability::try_from(cap_id).map_err(|_| Error::GetCapabilityId(name.to_string()))
+}
+
+/// Resolves a capability name to its ID like `name_to_capability`, but
+/// looks it up in a perfect hash map built along with the crate before
+/// libcap-ng, which is allocation-free and doesn't call into the library,
+/// for hot paths.
+///
+/// The resolver set with `set_name_resolver` is still asked first, with the
+/// name lowercased, and names are matched case-insensitively, as libcap-ng
+/// does. Only names missing from the map, like newer capabilities, go
+/// through libcap-ng, and `None` is returned if that fails too.
+pub fn lookup_name_fast(name: &str) -> Option<Capability> {
+    let mut buf = [0u8; MAX_NAME_LEN];
+    let owned;
+    let lower = match buf.get_mut(..name.len()) {
+        Some(lower) => {
+            lower.copy_from_slice(name.as_bytes());
+            lower.make_ascii_lowercase();
+            // Lowercasing ASCII bytes keeps the string valid UTF-8.
+            std::str::from_utf8(lower).ok()?
+        }
+        // Too long to be in the table, but the resolver may still know it.
+        None => {
+            owned = name.to_ascii_lowercase();
+            &owned
+        }
+    };
+
+    resolve(lower)
+        .or_else(|| NAME_TABLE.get(lower).copied())
+        .or_else(|| libcapng_name_to_capability(name).ok())
+}
+
+/// Returns the name of the capability with ID `capability`, like
+/// `"net_admin"`.
+pub fn capability_to_name(capability: Capability) -> Result<String> {
+    // Safe because this doesn't modify any local memory.
+    let name_ptr = unsafe { bindings::capng_capability_to_name(capability) };
+    if name_ptr.is_null() {
+        return Err(Error::NameToCapability(capability));
+    }
+    // Only safe if capng_capability_to_name behaves properly.
+    let name = unsafe { CStr::from_ptr(name_ptr).to_string_lossy().into_owned() };
+
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn concurrent_lookup_tests() {
+        let _hooks = crate::tests::lock_hooks();
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                thread::spawn(|| {
+                    for _ in 0..100 {
+                        assert_eq!(name_to_capability("net_admin").unwrap(), 12);
+                        assert_eq!(capability_to_name(12).unwrap(), "net_admin");
+                        assert_eq!(capability_info(12).unwrap().name, "net_admin");
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        match name_to_capability("bogus") {
+            Err(Error::GetCapabilityId(name)) => assert_eq!(name, "bogus"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(capability_to_name(1000).is_err());
+        assert!(capability_to_name(Capability::MAX).is_err());
+        assert!(name_to_capability("-1").is_err());
+        assert!(name_to_capability("").is_err());
+    }
+
+    #[test]
+    fn name_resolver_tests() {
+        let _hooks = crate::tests::lock_hooks();
+        assert!(name_to_capability("network-admin").is_err());
+
+        set_name_resolver(|name| match name {
+            "network-admin" => Some(crate::cap::NET_ADMIN),
+            _ => None,
+        });
+        assert_eq!(name_to_capability("network-admin").unwrap(), 12);
+        assert_eq!(name_to_capability("chown").unwrap(), 0);
+        assert!(name_to_capability("bogus").is_err());
+
+        // The resolver comes before the table too, even for names in it.
+        // Only tests holding the hooks lock resolve this one.
+        set_name_resolver(|name| match name {
+            "network-admin" => Some(crate::cap::NET_ADMIN),
+            "wake_alarm" => Some(crate::cap::KILL),
+            _ => None,
+        });
+        assert_eq!(lookup_name_fast("network-admin"), Some(12));
+        assert_eq!(lookup_name_fast("wake_alarm"), Some(5));
+        assert_eq!(lookup_name_fast("WAKE_ALARM"), Some(5));
+        assert_eq!(name_to_capability("WAKE_ALARM").unwrap(), 5);
+        assert_eq!(lookup_name_fast("Network-Admin"), Some(12));
+        assert_eq!(name_to_capability("Network-Admin").unwrap(), 12);
+
+        clear_name_resolver();
+        assert!(name_to_capability("network-admin").is_err());
+        assert_eq!(lookup_name_fast("network-admin"), None);
+        assert_eq!(lookup_name_fast("wake_alarm"), Some(35));
+    }
+
+    #[test]
+    fn lookup_name_fast_tests() {
+        let _hooks = crate::tests::lock_hooks();
+        assert_eq!(
+            NAME_TABLE.len(),
+            crate::cap::CHECKPOINT_RESTORE as usize + 1
+        );
+
+        for (&name, &cap) in NAME_TABLE.entries() {
+            assert!(name.len() <= MAX_NAME_LEN);
+            assert_eq!(name_to_capability(name).unwrap(), cap);
+            assert_eq!(lookup_name_fast(name), Some(cap));
+            assert_eq!(lookup_name_fast(&name.to_uppercase()), Some(cap));
+        }
+
+        assert_eq!(lookup_name_fast("bogus"), None);
+        assert_eq!(lookup_name_fast(""), None);
+        assert_eq!(lookup_name_fast("net_admin\0"), None);
+        assert_eq!(lookup_name_fast(&"x".repeat(MAX_NAME_LEN + 1)), None);
+    }
+
+    #[test]
+    fn to_capability_tests() {
+        let _hooks = crate::tests::lock_hooks();
+        assert_eq!(12u32.to_capability().unwrap(), 12);
+        assert_eq!(12.to_capability().unwrap(), 12);
+        assert_eq!("NET_ADMIN".to_capability().unwrap(), 12);
+        assert_eq!(String::from("net_admin").to_capability().unwrap(), 12);
+        assert_eq!((&&"kill").to_capability().unwrap(), 5);
+
+        match "bogus".to_capability() {
+            Err(Error::GetCapabilityId(name)) => assert_eq!(name, "bogus"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match (-1).to_capability() {
+            Err(Error::GetCapabilityId(name)) => assert_eq!(name, "-1"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,186 @@
+// Copyright (C) 2020 Red Hat, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+use crate::{Capability, CapabilitySet};
+
+/// A well-known privileged operation, for looking up the capability it
+/// requires.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Operation {
+    /// Binding a socket to a port below 1024.
+    BindPrivilegedPort,
+    /// Changing the owner or group of files.
+    ChangeFileOwnership,
+    /// Reading, writing or executing files regardless of their permissions.
+    BypassFilePermissions,
+    /// Loading and unloading kernel modules.
+    LoadKernelModule,
+    /// Using raw and packet sockets.
+    UseRawSockets,
+    /// Configuring network interfaces, routes and firewall rules.
+    ConfigureNetwork,
+    /// Sending signals to processes owned by other users.
+    SignalOtherUsers,
+    /// Changing the root directory with `chroot`.
+    ChangeRoot,
+    /// Tracing other processes with `ptrace`.
+    TraceProcesses,
+    /// Setting the system clock.
+    SetSystemTime,
+    /// Creating device nodes with `mknod`.
+    CreateDeviceNodes,
+    /// Changing the user IDs of the process.
+    ChangeUser,
+    /// Changing the group IDs of the process.
+    ChangeGroup,
+    /// Locking memory with `mlock`.
+    LockMemory,
+    /// Raising the scheduling priority of processes.
+    RaisePriority,
+    /// Exceeding resource limits.
+    OverrideResourceLimits,
+    /// Rebooting the system.
+    Reboot,
+    /// Mounting and unmounting filesystems.
+    MountFilesystems,
+    /// Reading the kernel log.
+    ReadKernelLog,
+    /// Loading BPF programs.
+    LoadBpfProgram,
+}
+
+/// Maps each operation to the capability it requires, by capability ID, and
+/// to the names it's looked up by, the first one being its canonical name.
+const OPERATIONS: &[(Operation, Capability, &[&str])] = &[
+    (
+        Operation::BindPrivilegedPort,
+        10,
+        &["bind_privileged_port", "bind_low_port"],
+    ), // CAP_NET_BIND_SERVICE
+    (
+        Operation::ChangeFileOwnership,
+        0,
+        &["change_file_ownership"],
+    ), // CAP_CHOWN
+    (
+        Operation::BypassFilePermissions,
+        1,
+        &["bypass_file_permissions"],
+    ), // CAP_DAC_OVERRIDE
+    (Operation::LoadKernelModule, 16, &["load_kernel_module"]), // CAP_SYS_MODULE
+    (Operation::UseRawSockets, 13, &["use_raw_sockets"]),       // CAP_NET_RAW
+    (Operation::ConfigureNetwork, 12, &["configure_network"]),  // CAP_NET_ADMIN
+    (Operation::SignalOtherUsers, 5, &["signal_other_users"]),  // CAP_KILL
+    (Operation::ChangeRoot, 18, &["change_root"]),              // CAP_SYS_CHROOT
+    (Operation::TraceProcesses, 19, &["trace_processes"]),      // CAP_SYS_PTRACE
+    (Operation::SetSystemTime, 25, &["set_system_time"]),       // CAP_SYS_TIME
+    (Operation::CreateDeviceNodes, 27, &["create_device_nodes"]), // CAP_MKNOD
+    (Operation::ChangeUser, 7, &["change_user"]),               // CAP_SETUID
+    (Operation::ChangeGroup, 6, &["change_group"]),             // CAP_SETGID
+    (Operation::LockMemory, 14, &["lock_memory"]),              // CAP_IPC_LOCK
+    (Operation::RaisePriority, 23, &["raise_priority"]),        // CAP_SYS_NICE
+    (
+        Operation::OverrideResourceLimits,
+        24,
+        &["override_resource_limits"],
+    ), // CAP_SYS_RESOURCE
+    (Operation::Reboot, 22, &["reboot"]),                       // CAP_SYS_BOOT
+    (Operation::MountFilesystems, 21, &["mount_filesystems"]),  // CAP_SYS_ADMIN
+    (Operation::ReadKernelLog, 34, &["read_kernel_log"]),       // CAP_SYSLOG
+    (Operation::LoadBpfProgram, 39, &["load_bpf_program"]),     // CAP_BPF
+];
+
+impl Operation {
+    /// Returns the capability needed to perform the operation.
+    pub fn capability(self) -> Capability {
+        OPERATIONS
+            .iter()
+            .find(|(op, _, _)| *op == self)
+            .map(|&(_, cap, _)| cap)
+            .expect("every operation has a capability")
+    }
+
+    /// Returns the canonical name of the operation, like
+    /// `"bind_privileged_port"`.
+    pub fn name(self) -> &'static str {
+        OPERATIONS
+            .iter()
+            .find(|(op, _, _)| *op == self)
+            .map(|&(_, _, names)| names[0])
+            .expect("every operation has a name")
+    }
+
+    /// Looks up an operation by its name or one of its aliases, like
+    /// `"bind_low_port"`.
+    pub fn from_name(name: &str) -> Option<Operation> {
+        OPERATIONS
+            .iter()
+            .find(|(_, _, names)| names.contains(&name))
+            .map(|&(op, _, _)| op)
+    }
+}
+
+/// Computes the minimal set of capabilities needed to perform all of `ops`.
+pub fn caps_for_operations(ops: &[Operation]) -> CapabilitySet {
+    ops.iter().map(|op| op.capability()).collect()
+}
+
+/// Suggests the capability that would likely have allowed the operation
+/// named `operation`, see `Operation::from_name`, to succeed, given that it
+/// failed with `errno`.
+///
+/// Only `EPERM` and `EACCES` hint at a missing capability, so `None` is
+/// returned for any other error, as well as for unknown operations. For
+/// example, `explain_denied(libc::EPERM, "bind_low_port")` suggests
+/// `CAP_NET_BIND_SERVICE`.
+pub fn explain_denied(errno: i32, operation: &str) -> Option<Capability> {
+    if errno != libc::EPERM && errno != libc::EACCES {
+        return None;
+    }
+
+    Operation::from_name(operation).map(Operation::capability)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capability_to_name;
+
+    #[test]
+    fn caps_for_operations_tests() {
+        let caps = caps_for_operations(&[
+            Operation::BindPrivilegedPort,
+            Operation::ChangeFileOwnership,
+            Operation::LoadKernelModule,
+            Operation::ChangeFileOwnership,
+        ]);
+        let names: Vec<String> = caps
+            .iter()
+            .map(|cap| capability_to_name(cap).unwrap())
+            .collect();
+        assert_eq!(names, vec!["chown", "net_bind_service", "sys_module"]);
+
+        assert!(caps_for_operations(&[]).is_empty());
+        for &(op, _, _) in OPERATIONS {
+            assert!(capability_to_name(op.capability()).is_ok());
+            assert_eq!(Operation::from_name(op.name()), Some(op));
+        }
+    }
+
+    #[test]
+    fn explain_denied_tests() {
+        assert_eq!(
+            explain_denied(libc::EPERM, "bind_low_port"),
+            Some(crate::cap::NET_BIND_SERVICE)
+        );
+        assert_eq!(
+            explain_denied(libc::EACCES, "bypass_file_permissions"),
+            Some(crate::cap::DAC_OVERRIDE)
+        );
+        assert_eq!(explain_denied(libc::ENOENT, "bind_low_port"), None);
+        assert_eq!(explain_denied(libc::EPERM, "bogus"), None);
+        assert_eq!(Operation::BindPrivilegedPort.name(), "bind_privileged_port");
+    }
+}
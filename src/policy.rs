@@ -0,0 +1,178 @@
+// Copyright (C) 2020 Red Hat, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+use crate::{get_caps_process, have_capability, with_saved_state, Capability, Result, Type};
+
+/// A declarative description of the capabilities a process is expected to
+/// hold, and the ones it must not hold.
+///
+/// Each entry pairs a capability with the vectors it applies to. A `Type`
+/// with several bits set is checked against each of those vectors.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CapPolicy {
+    /// Capabilities that must be present.
+    pub present: Vec<(Type, Capability)>,
+    /// Capabilities that must be absent.
+    pub absent: Vec<(Type, Capability)>,
+}
+
+impl CapPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requires `capability` to be present in the `which` vectors.
+    pub fn require(mut self, which: Type, capability: Capability) -> Self {
+        self.present.push((which, capability));
+        self
+    }
+
+    /// Requires `capability` to be absent from the `which` vectors.
+    pub fn forbid(mut self, which: Type, capability: Capability) -> Self {
+        self.absent.push((which, capability));
+        self
+    }
+}
+
+/// A single mismatch between a `CapPolicy` and the process capabilities.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PolicyViolation {
+    /// The capability is required but missing from the vector.
+    Missing(Type, Capability),
+    /// The capability is forbidden but present in the vector.
+    Unexpected(Type, Capability),
+}
+
+/// Checks the capabilities of the target process against `policy`, returning
+/// every violation found. An empty vector means the process complies.
+///
+/// The process capabilities are loaded with `get_caps_process`, but the
+/// previous libcap-ng state is restored before returning.
+pub fn verify_policy(policy: &CapPolicy) -> Result<Vec<PolicyViolation>> {
+    with_saved_state(|| {
+        get_caps_process()?;
+
+        let mut violations = Vec::new();
+        for &(which, cap) in &policy.present {
+            for t in which {
+                if !have_capability(t, cap) {
+                    violations.push(PolicyViolation::Missing(t, cap));
+                }
+            }
+        }
+        for &(which, cap) in &policy.absent {
+            for t in which {
+                if have_capability(t, cap) {
+                    violations.push(PolicyViolation::Unexpected(t, cap));
+                }
+            }
+        }
+
+        Ok(violations)
+    })
+}
+
+/// Capabilities accumulated from several parties, like the plugins of a
+/// host, to check all at once with `verify`, rather than failing on the
+/// first missing one.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CapRequirements {
+    policy: CapPolicy,
+}
+
+impl CapRequirements {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requires `capability` to be present in the `which` vectors.
+    pub fn require(mut self, capability: Capability, which: Type) -> Self {
+        self.policy = self.policy.require(which, capability);
+        self
+    }
+
+    /// Adds the requirements of `other`, e.g. to aggregate those of every
+    /// plugin.
+    pub fn merge(mut self, other: &CapRequirements) -> Self {
+        self.policy
+            .present
+            .extend(other.policy.present.iter().copied());
+        self
+    }
+
+    /// Checks the requirements against the target process, returning every
+    /// required capability missing from any of its vectors, sorted by ID and
+    /// without duplicates. An empty vector means all of them are met.
+    ///
+    /// Like `verify_policy`, the previous libcap-ng state is restored before
+    /// returning.
+    pub fn verify(&self) -> Result<Vec<Capability>> {
+        let mut missing: Vec<Capability> = verify_policy(&self.policy)?
+            .into_iter()
+            .filter_map(|v| match v {
+                PolicyViolation::Missing(_, cap) => Some(cap),
+                PolicyViolation::Unexpected(_, _) => None,
+            })
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+
+        Ok(missing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clear, have_capabilities, HaveCapsResult, Set};
+
+    #[test]
+    fn verify_policy_tests() {
+        get_caps_process().unwrap();
+        let held = have_capability(Type::PERMITTED, 0);
+
+        let policy = CapPolicy::new()
+            .require(Type::PERMITTED, 0)
+            .forbid(Type::PERMITTED, 0);
+
+        clear(Set::BOTH);
+        let violations = verify_policy(&policy).unwrap();
+        if held {
+            assert_eq!(
+                violations,
+                vec![PolicyViolation::Unexpected(Type::PERMITTED, 0)]
+            );
+        } else {
+            assert_eq!(
+                violations,
+                vec![PolicyViolation::Missing(Type::PERMITTED, 0)]
+            );
+        }
+
+        // The state the caller had set up must survive the check.
+        assert_eq!(have_capabilities(Set::BOTH).unwrap(), HaveCapsResult::NONE);
+    }
+
+    #[test]
+    fn cap_requirements_tests() {
+        get_caps_process().unwrap();
+        let permitted = crate::capabilities_in(Type::PERMITTED);
+
+        let mut requirements = CapRequirements::new();
+        for cap in 0..=crate::cap::CHECKPOINT_RESTORE {
+            requirements = requirements.require(cap, Type::PERMITTED);
+        }
+        let plugin = CapRequirements::new()
+            .require(crate::cap::CHOWN, Type::PERMITTED)
+            .require(1000, Type::PERMITTED | Type::EFFECTIVE);
+        requirements = requirements.merge(&plugin);
+
+        let mut expected: Vec<Capability> = (0..=crate::cap::CHECKPOINT_RESTORE)
+            .filter(|cap| !permitted.contains(cap))
+            .collect();
+        expected.push(1000);
+        assert_eq!(requirements.verify().unwrap(), expected);
+        assert!(CapRequirements::new().verify().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright (C) 2020 Red Hat, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+use std::fmt;
+use std::path::Path;
+
+use crate::{
+    cap, get_caps_process, have_capability, no_new_privs, setpid, supports_ambient,
+    with_saved_state, Result, Type, PROC_SELF,
+};
+
+/// The state of the prerequisites for capability work, see `preflight`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PreflightReport {
+    /// Whether procfs is mounted, which libcap-ng needs to read the process
+    /// capabilities.
+    pub procfs_mounted: bool,
+    /// Whether the linked libcap-ng is recent enough to handle ambient
+    /// capabilities and root IDs.
+    pub supports_ambient: bool,
+    /// Whether the process holds `CAP_SETPCAP` in its effective set, which
+    /// is needed to drop capabilities from the bounding set.
+    pub has_setpcap: bool,
+    /// Whether the no_new_privs flag is already set.
+    pub no_new_privs: bool,
+}
+
+impl PreflightReport {
+    /// Returns whether the process can read and reduce all of its
+    /// capabilities, including the bounding set.
+    pub fn is_ready(&self) -> bool {
+        self.procfs_mounted && self.has_setpcap
+    }
+}
+
+impl fmt::Display for PreflightReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let yes_no = |b| if b { "yes" } else { "no" };
+        write!(
+            f,
+            "procfs mounted: {}, ambient supported: {}, CAP_SETPCAP held: {}, no_new_privs set: {}",
+            yes_no(self.procfs_mounted),
+            yes_no(self.supports_ambient),
+            yes_no(self.has_setpcap),
+            yes_no(self.no_new_privs)
+        )
+    }
+}
+
+/// Checks the common prerequisites for capability work in one go, e.g. to
+/// log them when a service starts, before it drops its capabilities.
+///
+/// Every field is about the calling thread, even if another process was
+/// targeted with `setpid`. Failing prerequisites are reported, not returned
+/// as errors. Only failing to run the checks themselves is an error. The
+/// libcap-ng state, including the target pid, is left untouched.
+pub fn preflight() -> Result<PreflightReport> {
+    let has_setpcap = with_saved_state(|| {
+        setpid(0);
+        get_caps_process()?;
+        Ok(have_capability(Type::EFFECTIVE, cap!(SETPCAP)))
+    });
+
+    Ok(PreflightReport {
+        procfs_mounted: Path::new(PROC_SELF).exists(),
+        supports_ambient: supports_ambient(),
+        has_setpcap: has_setpcap.unwrap_or(false),
+        no_new_privs: no_new_privs()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preflight_tests() {
+        let status = std::fs::read_to_string("/proc/thread-self/status").unwrap();
+        let effective = status
+            .lines()
+            .find_map(|l| l.strip_prefix("CapEff:"))
+            .map(|m| u64::from_str_radix(m.trim(), 16).unwrap())
+            .unwrap();
+
+        let report = preflight().unwrap();
+        assert!(report.procfs_mounted);
+        assert_eq!(report.has_setpcap, effective & 1 << cap!(SETPCAP) != 0);
+        assert_eq!(report.no_new_privs, no_new_privs().unwrap());
+        assert_eq!(report.is_ready(), report.has_setpcap);
+        assert!(report.to_string().starts_with("procfs mounted: yes, "));
+
+        // The target pid doesn't matter, and is kept.
+        std::thread::spawn(move || {
+            setpid(crate::Pid::MAX);
+            assert_eq!(preflight().unwrap(), report);
+            assert!(get_caps_process().is_err());
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,501 @@
+// Copyright (C) 2020 Red Hat, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+
+use crate::{
+    apply, capability_to_name, caps_generation, get_caps_process, have_capability,
+    known_capabilities, setpid, supports_ambient, with_saved_state, Capability, CapabilitySet,
+    Error, Pid, Result, Set, Type,
+};
+
+/// A snapshot of the capabilities of a process.
+///
+/// Two snapshots are equal if they hold the same capabilities, regardless of
+/// when they were taken.
+#[derive(Clone, Debug, Default)]
+pub struct ProcessCaps {
+    pub effective: CapabilitySet,
+    pub permitted: CapabilitySet,
+    pub inheritable: CapabilitySet,
+    pub bounding: CapabilitySet,
+    /// Always empty if the linked libcap-ng doesn't support ambient
+    /// capabilities.
+    pub ambient: CapabilitySet,
+    /// The `caps_generation` the snapshot was taken at.
+    generation: u64,
+}
+
+impl PartialEq for ProcessCaps {
+    fn eq(&self, other: &Self) -> bool {
+        self.effective == other.effective
+            && self.permitted == other.permitted
+            && self.inheritable == other.inheritable
+            && self.bounding == other.bounding
+            && self.ambient == other.ambient
+    }
+}
+
+impl Eq for ProcessCaps {}
+
+impl ProcessCaps {
+    /// Takes a snapshot of the current libcap-ng state, as last loaded with
+    /// `get_caps_process` or configured through `update`.
+    pub fn from_state() -> Self {
+        ProcessCaps {
+            effective: CapabilitySet::from_state(Type::EFFECTIVE),
+            permitted: CapabilitySet::from_state(Type::PERMITTED),
+            inheritable: CapabilitySet::from_state(Type::INHERITABLE),
+            bounding: CapabilitySet::from_state(Type::BOUNDING_SET),
+            ambient: if supports_ambient() {
+                CapabilitySet::from_state(Type::AMBIENT)
+            } else {
+                CapabilitySet::empty()
+            },
+            generation: caps_generation(),
+        }
+    }
+
+    /// Returns whether the libcap-ng state may have changed since this
+    /// snapshot was taken from it, in which case it should be read again
+    /// before relying on the state.
+    ///
+    /// Snapshots returned by helpers that restore the previous state, like
+    /// `read_caps_pidfd`, are stale right away, as the state they were taken
+    /// from is gone.
+    pub fn is_stale(&self) -> bool {
+        self.generation != caps_generation()
+    }
+
+    /// Returns the vector named by `which`, which must be a single vector.
+    pub fn get(&self, which: Type) -> CapabilitySet {
+        match which {
+            Type::EFFECTIVE => self.effective,
+            Type::PERMITTED => self.permitted,
+            Type::INHERITABLE => self.inheritable,
+            Type::BOUNDING_SET => self.bounding,
+            Type::AMBIENT => self.ambient,
+            _ => CapabilitySet::empty(),
+        }
+    }
+}
+
+/// The capabilities of a process as raw bitmasks, where bit N stands for
+/// the capability with ID N, as in `capget`/`capset` and
+/// `/proc/<pid>/status`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CapMasks {
+    pub effective: u64,
+    pub permitted: u64,
+    pub inheritable: u64,
+    pub bounding: u64,
+    /// Always 0 if the linked libcap-ng doesn't support ambient
+    /// capabilities.
+    pub ambient: u64,
+}
+
+/// Reads the capabilities of the target process as raw bitmasks.
+///
+/// The previous libcap-ng state is restored before returning.
+pub fn capability_masks() -> Result<CapMasks> {
+    with_saved_state(|| {
+        get_caps_process()?;
+        let mask = |which| {
+            known_capabilities()
+                .filter(|&cap| have_capability(which, cap))
+                .fold(0u64, |mask, cap| mask | 1u64.checked_shl(cap).unwrap_or(0))
+        };
+
+        Ok(CapMasks {
+            effective: mask(Type::EFFECTIVE),
+            permitted: mask(Type::PERMITTED),
+            inheritable: mask(Type::INHERITABLE),
+            bounding: mask(Type::BOUNDING_SET),
+            ambient: if supports_ambient() {
+                mask(Type::AMBIENT)
+            } else {
+                0
+            },
+        })
+    })
+}
+
+/// Reads the capabilities of the target process and maps each capability
+/// present in any vector to the vectors it's in, e.g. `CAP_CHOWN` to
+/// `Type::EFFECTIVE | Type::PERMITTED`, for rendering a combined table.
+///
+/// Capabilities absent from every vector are left out. The ambient set is
+/// only considered if the linked libcap-ng supports it. The previous
+/// libcap-ng state is restored before returning.
+pub fn capability_matrix() -> Result<BTreeMap<Capability, Type>> {
+    let caps = with_saved_state(|| {
+        get_caps_process()?;
+        Ok(ProcessCaps::from_state())
+    })?;
+
+    let mut matrix = BTreeMap::new();
+    for which in Type::all() {
+        for cap in caps.get(which) {
+            *matrix.entry(cap).or_insert_with(Type::empty) |= which;
+        }
+    }
+
+    Ok(matrix)
+}
+
+/// Reads the capabilities of the target process and formats them like the
+/// kernel does in `/proc/<pid>/status`, e.g.:
+///
+/// ```text
+/// CapInh: 0000000000000000
+/// CapPrm: 000001ffffffffff
+/// CapEff: 000001ffffffffff
+/// CapBnd: 000001ffffffffff
+/// CapAmb: 0000000000000000
+/// ```
+///
+/// Names and masks are separated by a tab, and the lines come in the same
+/// order and end with a newline, so they can be compared as is with the ones
+/// in the real file. The previous libcap-ng state is restored before
+/// returning.
+pub fn caps_to_proc_format() -> Result<String> {
+    let masks = capability_masks()?;
+
+    Ok(format!(
+        "CapInh:\t{:016x}\nCapPrm:\t{:016x}\nCapEff:\t{:016x}\nCapBnd:\t{:016x}\nCapAmb:\t{:016x}\n",
+        masks.inheritable, masks.permitted, masks.effective, masks.bounding, masks.ambient
+    ))
+}
+
+/// Reads the capabilities of the target process and formats them as a JSON
+/// object, like:
+///
+/// ```text
+/// {"effective":["chown","kill"],"permitted":["chown","kill"],"inheritable":[],"bounding":["chown","kill"]}
+/// ```
+///
+/// Capabilities are named as by `capability_to_name` and sorted by ID, and
+/// the keys always come in this order, so the output is stable. The previous
+/// libcap-ng state is restored before returning.
+pub fn caps_to_json() -> Result<String> {
+    let caps = with_saved_state(|| {
+        get_caps_process()?;
+        Ok(ProcessCaps::from_state())
+    })?;
+
+    let vectors = [
+        ("effective", caps.effective),
+        ("permitted", caps.permitted),
+        ("inheritable", caps.inheritable),
+        ("bounding", caps.bounding),
+    ];
+    let mut json = String::from("{");
+    for (i, (key, set)) in vectors.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let names: Vec<String> = set
+            .iter()
+            .map(|cap| {
+                format!(
+                    "\"{}\"",
+                    capability_to_name(cap).unwrap_or_else(|_| cap.to_string())
+                )
+            })
+            .collect();
+        json.push_str(&format!("\"{}\":[{}]", key, names.join(",")));
+    }
+    json.push('}');
+
+    Ok(json)
+}
+
+/// Reads the capabilities of the process referred to by `pidfd`.
+///
+/// Going through `setpid` and `get_caps_process` leaves a window where the
+/// pid could be recycled by an unrelated process. Here, the pidfd pins the
+/// process identity: once the capabilities are read, the pidfd is used to
+/// check the process is still alive, which guarantees its pid wasn't reused
+/// in the meantime. That check reads the pidfd's fdinfo rather than
+/// signaling the process, so it works for processes the caller isn't allowed
+/// to signal.
+///
+/// The previous libcap-ng state, including the target pid, is restored
+/// before returning.
+pub fn read_caps_pidfd(pidfd: RawFd) -> Result<ProcessCaps> {
+    let pid = pidfd_pid(pidfd).ok_or(Error::GetPidfdTarget(pidfd))?;
+
+    let caps = with_saved_state(|| {
+        setpid(pid);
+        get_caps_process()?;
+        Ok(ProcessCaps::from_state())
+    })?;
+
+    if pidfd_pid(pidfd) != Some(pid) {
+        return Err(Error::GetPidfdTarget(pidfd));
+    }
+
+    Ok(caps)
+}
+
+/// Reads the pids listed in the `cgroup.procs` file of the cgroup at
+/// `cgroup_path`, like `/sys/fs/cgroup/system.slice/foo.service`.
+fn cgroup_pids(cgroup_path: &Path) -> Result<Vec<Pid>> {
+    let path = cgroup_path.join("cgroup.procs");
+    let procs = fs::read_to_string(&path).map_err(|_| Error::OpenFile(path))?;
+
+    Ok(procs
+        .lines()
+        .filter_map(|l| l.trim().parse().ok())
+        .collect())
+}
+
+/// Reads the capabilities of every process in the cgroup at `cgroup_path`,
+/// e.g. to audit what a container runs with.
+///
+/// Processes are listed from the cgroup's `cgroup.procs`, and each result is
+/// paired with its pid, so a process that exited or can't be inspected
+/// meanwhile doesn't prevent reading the others. Only failing to read
+/// `cgroup.procs` is an error. The previous libcap-ng state, including the
+/// target pid, is restored before returning.
+pub fn read_cgroup_caps(cgroup_path: &Path) -> Result<Vec<(Pid, Result<ProcessCaps>)>> {
+    let pids = cgroup_pids(cgroup_path)?;
+
+    with_saved_state(|| {
+        Ok(pids
+            .into_iter()
+            .map(|pid| {
+                setpid(pid);
+                let caps = get_caps_process().map(|()| ProcessCaps::from_state());
+                (pid, caps)
+            })
+            .collect())
+    })
+}
+
+/// Applies the capability sets of the libcap-ng state to every process in
+/// the cgroup at `cgroup_path`, pairing each pid with the result of `apply`.
+///
+/// `set` must be `Set::CAPS`, or `Error::UnsupportedSet` is returned before
+/// touching any process: libcap-ng always changes the bounding and ambient
+/// sets of the calling thread, whatever the target pid, so applying those
+/// would act on the caller once per process in the cgroup.
+///
+/// Reading the capabilities of other processes is allowed, see
+/// `read_cgroup_caps`, but the kernel only lets a thread change its own, so
+/// expect `Error::ApplyCapabilities` for every process but the calling
+/// thread. This is still useful to tell which processes could be updated,
+/// and the rest have to drop capabilities themselves. Only failing to read
+/// `cgroup.procs` is an error. The previous libcap-ng state, including the
+/// target pid, is restored before returning.
+pub fn apply_to_cgroup(cgroup_path: &Path, set: Set) -> Result<Vec<(Pid, Result<()>)>> {
+    if set != Set::CAPS {
+        return Err(Error::UnsupportedSet(set));
+    }
+    let pids = cgroup_pids(cgroup_path)?;
+
+    with_saved_state(|| {
+        Ok(pids
+            .into_iter()
+            .map(|pid| {
+                setpid(pid);
+                (pid, apply(set))
+            })
+            .collect())
+    })
+}
+
+/// Gets the pid of the process referred to by `pidfd`, if it's still alive.
+fn pidfd_pid(pidfd: RawFd) -> Option<Pid> {
+    let fdinfo = fs::read_to_string(format!("/proc/self/fdinfo/{}", pidfd)).ok()?;
+    let pid: Pid = fdinfo
+        .lines()
+        .find_map(|l| l.strip_prefix("Pid:"))?
+        .trim()
+        .parse()
+        .ok()?;
+
+    // The kernel reports -1 once the process is gone.
+    if pid > 0 {
+        Some(pid)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_caps_pidfd_tests() {
+        let pid = std::process::id() as Pid;
+        // Safe because pidfd_open doesn't modify any local memory.
+        let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) } as RawFd;
+        assert!(pidfd >= 0);
+
+        setpid(pid);
+        get_caps_process().unwrap();
+        let expected = ProcessCaps::from_state();
+        setpid(0);
+
+        assert_eq!(read_caps_pidfd(pidfd).unwrap(), expected);
+
+        // Safe because we own pidfd.
+        unsafe { libc::close(pidfd) };
+        match read_caps_pidfd(pidfd) {
+            Err(Error::GetPidfdTarget(fd)) => assert_eq!(fd, pidfd),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        // A pidfd outlives its process, which the fdinfo tells once it's
+        // reaped. Safe because the child exits right away.
+        let child = unsafe { libc::fork() };
+        assert!(child >= 0);
+        if child == 0 {
+            // Safe because we exit without running anything else.
+            unsafe { libc::_exit(0) };
+        }
+        // Safe because pidfd_open doesn't modify any local memory.
+        let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, child, 0) } as RawFd;
+        assert!(pidfd >= 0);
+        let mut status = 0;
+        // Safe because status is a valid pointer.
+        assert_eq!(unsafe { libc::waitpid(child, &mut status, 0) }, child);
+        assert!(read_caps_pidfd(pidfd).is_err());
+        // Safe because we own pidfd.
+        unsafe { libc::close(pidfd) };
+    }
+
+    #[test]
+    fn cgroup_tests() {
+        if crate::tests::skip_unprivileged() {
+            return;
+        }
+
+        // Only cgroup.procs is read, so a plain directory stands in for a
+        // cgroup.
+        let cgroup = std::env::temp_dir().join(format!("capng-{}-cgroup", std::process::id()));
+        fs::create_dir_all(&cgroup).unwrap();
+        let own = std::process::id() as Pid;
+        fs::write(cgroup.join("cgroup.procs"), format!("1\n{}\n", own)).unwrap();
+
+        std::thread::spawn(move || {
+            get_caps_process().unwrap();
+            let caps = ProcessCaps::from_state();
+
+            let read = read_cgroup_caps(&cgroup).unwrap();
+            assert_eq!(read.len(), 2);
+            assert_eq!(read[0].0, 1);
+            assert!(read[0].1.is_ok());
+            assert_eq!(read[1].0, own);
+            assert_eq!(read[1].1.as_ref().unwrap().permitted, caps.permitted);
+
+            // The main thread is a different thread, so neither process can
+            // be changed from here.
+            let applied = apply_to_cgroup(&cgroup, Set::CAPS).unwrap();
+            assert_eq!(
+                applied.iter().map(|(pid, _)| *pid).collect::<Vec<_>>(),
+                [1, own]
+            );
+            assert!(applied.iter().all(|(_, r)| r.is_err()));
+            assert_eq!(ProcessCaps::from_state(), caps);
+
+            // The bounding and ambient sets would be applied to this thread.
+            for set in [Set::BOUNDS, Set::BOTH, Set::AMBIENT, Set::ALL] {
+                match apply_to_cgroup(&cgroup, set) {
+                    Err(Error::UnsupportedSet(s)) => assert_eq!(s, set),
+                    r => panic!("unexpected result: {:?}", r),
+                }
+            }
+
+            fs::remove_dir_all(&cgroup).unwrap();
+            match read_cgroup_caps(&cgroup) {
+                Err(Error::OpenFile(path)) => assert_eq!(path, cgroup.join("cgroup.procs")),
+                r => panic!("unexpected result: {:?}", r),
+            }
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn capability_masks_tests() {
+        let status = fs::read_to_string("/proc/thread-self/status").unwrap();
+        let field = |name: &str| {
+            status
+                .lines()
+                .find_map(|l| l.strip_prefix(name))
+                .map(|v| u64::from_str_radix(v.trim(), 16).unwrap())
+                .unwrap()
+        };
+
+        let masks = capability_masks().unwrap();
+        assert_eq!(masks.effective, field("CapEff:"));
+        assert_eq!(masks.permitted, field("CapPrm:"));
+        assert_eq!(masks.inheritable, field("CapInh:"));
+        assert_eq!(masks.bounding, field("CapBnd:"));
+        assert_eq!(masks.ambient, field("CapAmb:"));
+    }
+
+    #[test]
+    fn capability_matrix_tests() {
+        let masks = capability_masks().unwrap();
+        let matrix = capability_matrix().unwrap();
+
+        for cap in 0..64 {
+            let mut expected = Type::empty();
+            for (which, mask) in [
+                (Type::EFFECTIVE, masks.effective),
+                (Type::PERMITTED, masks.permitted),
+                (Type::INHERITABLE, masks.inheritable),
+                (Type::BOUNDING_SET, masks.bounding),
+                (Type::AMBIENT, masks.ambient),
+            ] {
+                if mask & 1 << cap != 0 {
+                    expected |= which;
+                }
+            }
+            assert_eq!(
+                matrix.get(&cap).copied(),
+                Some(expected).filter(|t| !t.is_empty())
+            );
+        }
+    }
+
+    #[test]
+    fn caps_to_json_tests() {
+        if crate::tests::skip_unprivileged() {
+            return;
+        }
+
+        get_caps_process().unwrap();
+        let caps = ProcessCaps::from_state();
+        let json = caps_to_json().unwrap();
+
+        assert!(json.starts_with("{\"effective\":[\"chown\","));
+        assert!(json.contains(",\"inheritable\":[],\"bounding\":["));
+        assert!(json.ends_with("\"checkpoint_restore\"]}"));
+        assert_eq!(json.matches("\"sys_admin\"").count(), 3);
+        assert_eq!(
+            json.matches(',').count() + 1,
+            caps.effective.len() + caps.permitted.len() + caps.bounding.len() + 1
+        );
+    }
+
+    #[test]
+    fn caps_to_proc_format_tests() {
+        let status = fs::read_to_string("/proc/thread-self/status").unwrap();
+        let expected: String = status
+            .lines()
+            .filter(|l| l.starts_with("Cap"))
+            .map(|l| format!("{}\n", l))
+            .collect();
+
+        assert_eq!(caps_to_proc_format().unwrap(), expected);
+    }
+}
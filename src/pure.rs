@@ -0,0 +1,480 @@
+// Copyright (C) 2020 Red Hat, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+//! Process capabilities through the raw `capget`/`capset` syscalls.
+//!
+//! This mirrors the process-capability part of the crate's API (`setpid`,
+//! `clear`, `fill`, `update`, `get_caps_process`, `have_capability` and
+//! `apply`) without calling into libcap-ng. Like libcap-ng, it keeps a
+//! per-thread in-memory state which is loaded from and applied to the kernel,
+//! but that state is separate from libcap-ng's, so the two APIs can't be
+//! mixed. File capabilities and `change_id` are only available through
+//! libcap-ng.
+
+use std::cell::RefCell;
+use std::fs;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::abi::{
+    CapUserData, CapUserHeader, LINUX_CAPABILITY_VERSION_1, LINUX_CAPABILITY_VERSION_2,
+    LINUX_CAPABILITY_VERSION_3,
+};
+use crate::{Action, CUpdate, CapMasks, Capability, Error, Pid, Result, Set, Type};
+
+#[derive(Default)]
+struct State {
+    pid: Pid,
+    effective: u64,
+    permitted: u64,
+    inheritable: u64,
+    bounding: u64,
+    ambient: u64,
+}
+
+impl State {
+    fn vector(&mut self, which: Type) -> Option<&mut u64> {
+        match which {
+            Type::EFFECTIVE => Some(&mut self.effective),
+            Type::PERMITTED => Some(&mut self.permitted),
+            Type::INHERITABLE => Some(&mut self.inheritable),
+            Type::BOUNDING_SET => Some(&mut self.bounding),
+            Type::AMBIENT => Some(&mut self.ambient),
+            _ => None,
+        }
+    }
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+/// The cached result of `capability_abi_version`, or 0 until probed.
+static ABI_VERSION: AtomicU32 = AtomicU32::new(0);
+
+/// Returns the capability ABI version the running kernel prefers, one of
+/// the `LINUX_CAPABILITY_VERSION_*` constants in `abi`.
+///
+/// Version 1 only carries capabilities 0 to 31 in a single `CapUserData`,
+/// while versions 2 and 3 carry all 64 in two, so `capget_pid` and `apply`
+/// use this to pick the layout the kernel understands rather than failing,
+/// or silently dropping the upper capabilities. The kernel is probed once,
+/// by asking it for an unknown version, and the result is cached.
+pub fn capability_abi_version() -> Result<u32> {
+    let cached = ABI_VERSION.load(Ordering::Relaxed);
+    if cached != 0 {
+        return Ok(cached);
+    }
+
+    let mut header = CapUserHeader { version: 0, pid: 0 };
+    // Safe because the kernel only writes its preferred version into header
+    // when given an unknown one, and doesn't touch the null data pointer.
+    unsafe {
+        libc::syscall(
+            libc::SYS_capget,
+            &mut header,
+            std::ptr::null_mut::<CapUserData>(),
+        )
+    };
+
+    match header.version {
+        LINUX_CAPABILITY_VERSION_1 | LINUX_CAPABILITY_VERSION_2 | LINUX_CAPABILITY_VERSION_3 => {
+            ABI_VERSION.store(header.version, Ordering::Relaxed);
+            Ok(header.version)
+        }
+        _ => Err(Error::GetCapabilityAbiVersion),
+    }
+}
+
+/// Returns the highest capability supported by the running kernel, as
+/// cached by `kernel_last_cap`, or the highest known to the crate if procfs
+/// isn't available to tell.
+fn last_cap() -> Capability {
+    crate::kernel_last_cap().unwrap_or(crate::cap::CHECKPOINT_RESTORE)
+}
+
+/// Returns a mask with every capability the running kernel supports.
+fn all_caps() -> u64 {
+    u64::MAX >> (63 - last_cap())
+}
+
+fn is_self(pid: Pid) -> bool {
+    // Safe because these don't modify any local memory.
+    pid == 0 || pid == unsafe { libc::syscall(libc::SYS_gettid) } as Pid
+}
+
+/// Sets the process whose capabilities are loaded by `get_caps_process`.
+/// Pid 0 stands for the calling thread.
+pub fn setpid(pid: Pid) {
+    STATE.with(|s| s.borrow_mut().pid = pid);
+}
+
+pub fn clear(set: Set) {
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        if set.contains(Set::CAPS) {
+            s.effective = 0;
+            s.permitted = 0;
+            s.inheritable = 0;
+        }
+        if set.contains(Set::BOUNDS) {
+            s.bounding = 0;
+        }
+        if set.contains(Set::AMBIENT) {
+            s.ambient = 0;
+        }
+    });
+}
+
+pub fn fill(set: Set) {
+    let all = all_caps();
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        if set.contains(Set::CAPS) {
+            s.effective = all;
+            s.permitted = all;
+            s.inheritable = all;
+        }
+        if set.contains(Set::BOUNDS) {
+            s.bounding = all;
+        }
+        if set.contains(Set::AMBIENT) {
+            s.ambient = all;
+        }
+    });
+}
+
+pub fn update(updates: Vec<CUpdate>) -> Result<()> {
+    let last = last_cap();
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        for u in updates {
+            if u.capability > last {
+                return Err(Error::UpdateCapability(u.capability));
+            }
+            for t in u.cap_type {
+                let vector = s.vector(t).unwrap();
+                match u.action {
+                    Action::ADD => *vector |= 1 << u.capability,
+                    Action::DROP => *vector &= !(1 << u.capability),
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Loads the capabilities of the target process from the kernel.
+///
+/// Unlike with `capget_pid`, the bounding and ambient sets of another
+/// process are loaded too, from `/proc/<pid>/status`. As that read is
+/// separate from `capget`, the other sets are read from the status file as
+/// well, and `Error::GetProcessCapabilities` is returned if they differ, in
+/// case the pid was recycled in between.
+pub fn get_caps_process() -> Result<()> {
+    let pid = STATE.with(|s| s.borrow().pid);
+    let mut masks = capget_pid(pid)?;
+    if !is_self(pid) {
+        let status = read_status_masks(pid).ok_or(Error::GetProcessCapabilities)?;
+        if (status.effective, status.permitted, status.inheritable)
+            != (masks.effective, masks.permitted, masks.inheritable)
+        {
+            return Err(Error::GetProcessCapabilities);
+        }
+        masks.bounding = status.bounding;
+        masks.ambient = status.ambient;
+    }
+
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        s.effective = masks.effective;
+        s.permitted = masks.permitted;
+        s.inheritable = masks.inheritable;
+        s.bounding = masks.bounding;
+        s.ambient = masks.ambient;
+    });
+
+    Ok(())
+}
+
+/// Reads the capabilities of the process `pid` straight from the kernel with
+/// `capget`, without going through nor modifying the per-thread state. Pid 0
+/// stands for the calling thread.
+///
+/// This is meant for reading the capabilities of many processes in a row,
+/// so it only issues `capget`, which doesn't report the bounding and ambient
+/// sets. Those are read with `prctl` for the calling thread, and left empty
+/// for any other process, whose ones are only exposed through procfs, see
+/// `get_caps_process`.
+pub fn capget_pid(pid: Pid) -> Result<CapMasks> {
+    let mut header = CapUserHeader {
+        version: capability_abi_version()?,
+        pid,
+    };
+    // With version 1, the kernel only fills the first element, and the
+    // upper capabilities, which it doesn't support anyway, are left empty.
+    let mut data = [CapUserData::default(); 2];
+    // Safe because header and data are valid for every layout, which use at
+    // most two data elements.
+    let ret = unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) };
+    if ret != 0 {
+        return Err(Error::GetProcessCapabilities);
+    }
+
+    let (bounding, ambient) = if is_self(pid) {
+        read_own_bounding_ambient()
+    } else {
+        (0, 0)
+    };
+
+    Ok(CapMasks {
+        effective: join(data[0].effective, data[1].effective),
+        permitted: join(data[0].permitted, data[1].permitted),
+        inheritable: join(data[0].inheritable, data[1].inheritable),
+        bounding,
+        ambient,
+    })
+}
+
+fn join(low: u32, high: u32) -> u64 {
+    u64::from(high) << 32 | u64::from(low)
+}
+
+fn read_own_bounding_ambient() -> (u64, u64) {
+    let mut bounding = 0;
+    let mut ambient = 0;
+    for cap in 0..=last_cap() {
+        let cap_arg = cap as libc::c_ulong;
+        // Safe because these don't modify any local memory.
+        if unsafe { libc::prctl(libc::PR_CAPBSET_READ, cap_arg, 0, 0, 0) } == 1 {
+            bounding |= 1 << cap;
+        }
+        let is_set = libc::PR_CAP_AMBIENT_IS_SET as libc::c_ulong;
+        if unsafe { libc::prctl(libc::PR_CAP_AMBIENT, is_set, cap_arg, 0, 0) } == 1 {
+            ambient |= 1 << cap;
+        }
+    }
+    (bounding, ambient)
+}
+
+/// Reads the capabilities of `pid` from `/proc/<pid>/status`, which is the
+/// only place the kernel exposes other processes' bounding and ambient sets.
+fn read_status_masks(pid: Pid) -> Option<CapMasks> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let mask = |key: &str| {
+        status
+            .lines()
+            .find_map(|l| l.strip_prefix(key))
+            .and_then(|m| u64::from_str_radix(m.trim(), 16).ok())
+    };
+    Some(CapMasks {
+        effective: mask("CapEff:")?,
+        permitted: mask("CapPrm:")?,
+        inheritable: mask("CapInh:")?,
+        bounding: mask("CapBnd:")?,
+        // Kernels without ambient capabilities don't report CapAmb.
+        ambient: mask("CapAmb:").unwrap_or(0),
+    })
+}
+
+pub fn have_capability(which: Type, capability: Capability) -> bool {
+    if capability >= 64 {
+        return false;
+    }
+    STATE.with(|s| {
+        s.borrow_mut()
+            .vector(which)
+            .is_some_and(|v| *v & (1 << capability) != 0)
+    })
+}
+
+/// Applies the in-memory state to the calling thread. Like with libcap-ng,
+/// capabilities can only be applied to oneself.
+pub fn apply(set: Set) -> Result<()> {
+    let (pid, effective, permitted, inheritable, bounding, ambient) = STATE.with(|s| {
+        let s = s.borrow();
+        (
+            s.pid,
+            s.effective,
+            s.permitted,
+            s.inheritable,
+            s.bounding,
+            s.ambient,
+        )
+    });
+    if !is_self(pid) {
+        return Err(Error::ApplyCapabilities);
+    }
+
+    // Drop bounding capabilities first, as that requires CAP_SETPCAP, which
+    // applying the other vectors could remove.
+    if set.contains(Set::BOUNDS) {
+        for cap in 0..=last_cap() {
+            let cap_arg = cap as libc::c_ulong;
+            if bounding & (1 << cap) != 0 {
+                continue;
+            }
+            // Safe because these don't modify any local memory.
+            if unsafe { libc::prctl(libc::PR_CAPBSET_READ, cap_arg, 0, 0, 0) } == 1
+                && unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap_arg, 0, 0, 0) } != 0
+            {
+                return Err(Error::ApplyCapabilities);
+            }
+        }
+    }
+
+    if set.contains(Set::CAPS) {
+        let mut header = CapUserHeader {
+            version: capability_abi_version()?,
+            pid: 0,
+        };
+        let data = [
+            CapUserData {
+                effective: effective as u32,
+                permitted: permitted as u32,
+                inheritable: inheritable as u32,
+            },
+            CapUserData {
+                effective: (effective >> 32) as u32,
+                permitted: (permitted >> 32) as u32,
+                inheritable: (inheritable >> 32) as u32,
+            },
+        ];
+        // Safe because header and data are valid for every layout, and the
+        // kernel only reads from data. With version 1, it ignores the second
+        // element.
+        let ret = unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) };
+        if ret != 0 {
+            return Err(Error::ApplyCapabilities);
+        }
+    }
+
+    if set.contains(Set::AMBIENT) {
+        let clear_all = libc::PR_CAP_AMBIENT_CLEAR_ALL as libc::c_ulong;
+        // Safe because this doesn't modify any local memory.
+        if unsafe { libc::prctl(libc::PR_CAP_AMBIENT, clear_all, 0, 0, 0) } != 0 {
+            return Err(Error::ApplyCapabilities);
+        }
+        let raise = libc::PR_CAP_AMBIENT_RAISE as libc::c_ulong;
+        for cap in (0..64).filter(|cap| ambient & (1 << cap) != 0) {
+            // Safe because this doesn't modify any local memory.
+            if unsafe { libc::prctl(libc::PR_CAP_AMBIENT, raise, cap as libc::c_ulong, 0, 0) } != 0
+            {
+                return Err(Error::ApplyCapabilities);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_libcapng_tests() {
+        get_caps_process().unwrap();
+        crate::get_caps_process().unwrap();
+        for t in Type::all() {
+            for cap in crate::known_capabilities() {
+                assert_eq!(
+                    have_capability(t, cap),
+                    crate::have_capability(t, cap),
+                    "{:?} {}",
+                    t,
+                    cap
+                );
+            }
+        }
+
+        // Both states are per thread, so target the main thread from a fresh
+        // one.
+        let main = std::process::id() as Pid;
+        std::thread::spawn(move || {
+            setpid(main);
+            get_caps_process().unwrap();
+            crate::setpid(main);
+            let expected = crate::capability_masks().unwrap();
+            STATE.with(|s| {
+                let s = s.borrow();
+                assert_eq!(s.effective, expected.effective);
+                assert_eq!(s.permitted, expected.permitted);
+                assert_eq!(s.inheritable, expected.inheritable);
+                assert_eq!(s.bounding, expected.bounding);
+                assert_eq!(s.ambient, expected.ambient);
+            });
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn capget_pid_tests() {
+        let masks = capget_pid(0).unwrap();
+        assert_eq!(masks, crate::capability_masks().unwrap());
+
+        // Another thread, whose bounding and ambient sets capget doesn't
+        // report.
+        let main = capget_pid(std::process::id() as Pid).unwrap();
+        assert_eq!(
+            (main.effective, main.permitted, main.inheritable),
+            (masks.effective, masks.permitted, masks.inheritable)
+        );
+        assert_eq!((main.bounding, main.ambient), (0, 0));
+        let status = read_status_masks(std::process::id() as Pid).unwrap();
+        assert_eq!(status.effective, main.effective);
+        assert_eq!(status.bounding, masks.bounding);
+
+        assert!(capget_pid(Pid::MAX).is_err());
+        assert_eq!(STATE.with(|s| s.borrow().pid), 0);
+    }
+
+    #[test]
+    fn capability_abi_version_tests() {
+        // Every kernel with capabilities above 31 prefers version 3.
+        assert_eq!(
+            capability_abi_version().unwrap(),
+            LINUX_CAPABILITY_VERSION_3
+        );
+        assert_eq!(
+            ABI_VERSION.load(Ordering::Relaxed),
+            LINUX_CAPABILITY_VERSION_3
+        );
+        assert_eq!(
+            capability_abi_version().unwrap(),
+            LINUX_CAPABILITY_VERSION_3
+        );
+    }
+
+    #[test]
+    fn apply_tests() {
+        get_caps_process().unwrap();
+        if !have_capability(Type::EFFECTIVE, 0) {
+            return;
+        }
+
+        // Capabilities are per-thread, so this only affects the test thread.
+        update(vec![CUpdate {
+            action: Action::DROP,
+            cap_type: Type::EFFECTIVE,
+            capability: 0,
+        }])
+        .unwrap();
+        apply(Set::CAPS).unwrap();
+
+        clear(Set::BOTH);
+        get_caps_process().unwrap();
+        assert!(!have_capability(Type::EFFECTIVE, 0));
+        assert!(have_capability(Type::PERMITTED, 0));
+
+        fill(Set::CAPS);
+        assert!(have_capability(Type::INHERITABLE, last_cap()));
+        assert!(update(vec![CUpdate {
+            action: Action::ADD,
+            cap_type: Type::EFFECTIVE,
+            capability: last_cap() + 1,
+        }])
+        .is_err());
+    }
+}
@@ -0,0 +1,122 @@
+// Copyright (C) 2020 Red Hat, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+use std::sync::RwLock;
+
+use crate::{cap, CapState, Capability};
+
+type WeightFn = Box<dyn Fn(Capability) -> Option<u32> + Send + Sync>;
+
+/// The weights set with `set_privilege_weights`, if any.
+static WEIGHTS: RwLock<Option<WeightFn>> = RwLock::new(None);
+
+/// The weight of capabilities missing from `PRIVILEGE_WEIGHTS`.
+const DEFAULT_WEIGHT: u32 = 10;
+
+/// How dangerous each capability is in the hands of an attacker, roughly by
+/// how directly it leads to full control of the system.
+const PRIVILEGE_WEIGHTS: &[(Capability, u32)] = &[
+    (cap::SYS_ADMIN, 100),
+    (cap::SYS_MODULE, 95),
+    (cap::SYS_RAWIO, 90),
+    (cap::SYS_PTRACE, 85),
+    (cap::DAC_OVERRIDE, 80),
+    (cap::SETUID, 80),
+    (cap::SETPCAP, 75),
+    (cap::SETFCAP, 75),
+    (cap::BPF, 75),
+    (cap::DAC_READ_SEARCH, 70),
+    (cap::SETGID, 70),
+    (cap::MAC_ADMIN, 70),
+    (cap::MAC_OVERRIDE, 70),
+    (cap::NET_ADMIN, 60),
+    (cap::FOWNER, 50),
+    (cap::SYS_BOOT, 50),
+    (cap::PERFMON, 45),
+    (cap::CHOWN, 40),
+    (cap::NET_RAW, 40),
+    (cap::CHECKPOINT_RESTORE, 40),
+    (cap::KILL, 30),
+    (cap::SYS_TIME, 30),
+    (cap::AUDIT_CONTROL, 30),
+    (cap::FSETID, 20),
+    (cap::SYS_CHROOT, 20),
+    (cap::MKNOD, 20),
+    (cap::NET_BIND_SERVICE, 5),
+];
+
+/// Registers `f` to weigh capabilities for `privilege_score`, for the whole
+/// process. Capabilities `f` returns `None` for get their weight from the
+/// crate's table as usual.
+///
+/// This replaces any weights set before.
+pub fn set_privilege_weights(f: impl Fn(Capability) -> Option<u32> + Send + Sync + 'static) {
+    *WEIGHTS.write().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(f));
+}
+
+/// Unregisters the weights set with `set_privilege_weights`, if any.
+pub fn clear_privilege_weights() {
+    *WEIGHTS.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Scores how privileged `caps` is, to rank processes by how dangerous their
+/// capabilities are, e.g. `CapState::capture` ones.
+///
+/// The score is the sum of the weights of the capabilities in the effective
+/// set, from `CAP_SYS_ADMIN`, the highest, down to harmless ones like
+/// `CAP_NET_BIND_SERVICE`, so an empty set scores 0. Weights come from the
+/// crate's table, unless overridden with `set_privilege_weights`.
+pub fn privilege_score(caps: &CapState) -> u32 {
+    let weights = WEIGHTS.read().unwrap_or_else(|e| e.into_inner());
+
+    caps.effective
+        .iter()
+        .map(|cap| {
+            weights
+                .as_ref()
+                .and_then(|f| f(cap))
+                .unwrap_or_else(|| default_weight(cap))
+        })
+        .fold(0, u32::saturating_add)
+}
+
+fn default_weight(cap: Capability) -> u32 {
+    PRIVILEGE_WEIGHTS
+        .iter()
+        .find(|&&(c, _)| c == cap)
+        .map_or(DEFAULT_WEIGHT, |&(_, weight)| weight)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn privilege_score_tests() {
+        let _hooks = crate::tests::lock_hooks();
+        let state = |caps: &[Capability]| CapState {
+            effective: caps.iter().collect(),
+            permitted: caps.iter().collect(),
+            ..Default::default()
+        };
+
+        assert_eq!(privilege_score(&CapState::default()), 0);
+        assert_eq!(privilege_score(&state(&[cap::SYS_ADMIN])), 100);
+        assert_eq!(privilege_score(&state(&[cap::CHOWN, cap::KILL])), 70);
+        assert_eq!(privilege_score(&state(&[cap::LEASE])), DEFAULT_WEIGHT);
+        assert!(
+            privilege_score(&state(&[cap::SYS_ADMIN])) > privilege_score(&state(&[cap::CHOWN]))
+        );
+
+        // Only the effective set counts.
+        let mut caps = state(&[]);
+        caps.permitted.insert(cap::SYS_ADMIN);
+        assert_eq!(privilege_score(&caps), 0);
+
+        set_privilege_weights(|cap| if cap == cap::CHOWN { Some(1000) } else { None });
+        assert_eq!(privilege_score(&state(&[cap::CHOWN, cap::KILL])), 1030);
+        clear_privilege_weights();
+        assert_eq!(privilege_score(&state(&[cap::CHOWN, cap::KILL])), 70);
+    }
+}
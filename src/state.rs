@@ -0,0 +1,702 @@
+// Copyright (C) 2020 Red Hat, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+use std::cell::RefCell;
+use std::env;
+use std::fmt;
+
+use crate::{
+    apply, caps_generation, clear, get_caps_process, have_capability_uncached, known_capabilities,
+    setpid, supports_ambient, update, with_saved_state, Action, CUpdate, Capability, CapabilitySet,
+    Error, ProcessCaps, Result, Set, Type,
+};
+
+/// A complete capability configuration, as a plain value that can be
+/// stored, compared and restored later.
+///
+/// Unlike `CapngState`, which is an opaque copy of the libcap-ng state, this
+/// holds the capabilities themselves.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CapState {
+    pub effective: CapabilitySet,
+    pub permitted: CapabilitySet,
+    pub inheritable: CapabilitySet,
+    pub bounding: CapabilitySet,
+    /// Always empty if the linked libcap-ng doesn't support ambient
+    /// capabilities.
+    pub ambient: CapabilitySet,
+}
+
+/// Identifies serialized `CapState`s, see `CapState::to_bytes`.
+const STATE_MAGIC: &[u8; 4] = b"CAPS";
+/// The current version of the serialization format.
+const STATE_VERSION: u8 = 1;
+
+impl CapState {
+    /// Reads the capabilities of the target process.
+    ///
+    /// The previous libcap-ng state is restored before returning.
+    pub fn capture() -> Result<CapState> {
+        with_saved_state(|| {
+            get_caps_process()?;
+            Ok(CapState::from(ProcessCaps::from_state()))
+        })
+    }
+
+    /// Serializes the configuration into a compact binary format, e.g. to
+    /// store it or send it to another host.
+    ///
+    /// The format starts with the magic `CAPS` and a version byte, followed
+    /// by the effective, permitted, inheritable, bounding and ambient masks as
+    /// 64-bit little-endian integers, where bit N stands for capability N. As
+    /// every possible capability ID has a bit, capabilities unknown to the
+    /// reader, e.g. from a newer kernel, are kept as is.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(STATE_MAGIC.len() + 1 + 5 * 8);
+        bytes.extend_from_slice(STATE_MAGIC);
+        bytes.push(STATE_VERSION);
+        for (_, set) in self.vectors() {
+            bytes.extend_from_slice(&set.bits().to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Deserializes a configuration written by `to_bytes`, failing with
+    /// `Error::InvalidStateBytes` if `bytes` hold anything else, including
+    /// a format version this crate doesn't know.
+    pub fn from_bytes(bytes: &[u8]) -> Result<CapState> {
+        let masks = bytes
+            .strip_prefix(&STATE_MAGIC[..])
+            .and_then(|rest| rest.strip_prefix(&[STATE_VERSION]))
+            .filter(|masks| masks.len() == 5 * 8)
+            .ok_or(Error::InvalidStateBytes)?;
+
+        let mask = |index: usize| {
+            let mut bits = [0; 8];
+            bits.copy_from_slice(&masks[index * 8..(index + 1) * 8]);
+            CapabilitySet::from_bits(u64::from_le_bytes(bits))
+        };
+
+        Ok(CapState {
+            effective: mask(0),
+            permitted: mask(1),
+            inheritable: mask(2),
+            bounding: mask(3),
+            ambient: mask(4),
+        })
+    }
+
+    /// Formats the effective, permitted and inheritable sets in the notation
+    /// of `getpcaps`, like `cap_chown,cap_kill+ep cap_net_raw+p`.
+    ///
+    /// Capabilities sharing the same flags are grouped, and groups are
+    /// ordered by their lowest capability ID. Without any capability, this is
+    /// `=`.
+    pub fn to_getpcaps_string(&self) -> String {
+        let mut groups: Vec<(&str, CapabilitySet)> = Vec::new();
+        for cap in self
+            .effective
+            .union(self.permitted)
+            .union(self.inheritable)
+            .iter()
+        {
+            let flags = match (
+                self.effective.contains(cap),
+                self.inheritable.contains(cap),
+                self.permitted.contains(cap),
+            ) {
+                (true, true, true) => "eip",
+                (true, true, false) => "ei",
+                (true, false, true) => "ep",
+                (true, false, false) => "e",
+                (false, true, true) => "ip",
+                (false, true, false) => "i",
+                (false, false, _) => "p",
+            };
+            match groups.iter_mut().find(|(f, _)| *f == flags) {
+                Some((_, set)) => {
+                    set.insert(cap);
+                }
+                None => groups.push((flags, [cap].iter().collect())),
+            }
+        }
+
+        if groups.is_empty() {
+            return "=".to_string();
+        }
+        groups
+            .iter()
+            .map(|(flags, set)| format!("{}+{}", set, flags))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Applies this configuration to the target process, replacing all of
+    /// its capabilities. The libcap-ng state is left holding it.
+    ///
+    /// The ambient set is only cleared and applied if the linked libcap-ng
+    /// supports it, and ignored otherwise.
+    pub fn restore(&self) -> Result<()> {
+        let ambient = supports_ambient();
+        let mut set = Set::BOTH;
+        if ambient {
+            set |= Set::AMBIENT;
+        }
+        clear(set);
+
+        let mut updates = Vec::new();
+        for (which, caps) in self.vectors() {
+            if which == Type::AMBIENT && !ambient {
+                continue;
+            }
+            updates.extend(caps.iter().map(|capability| CUpdate {
+                action: Action::ADD,
+                cap_type: which,
+                capability,
+            }));
+        }
+        update(updates)?;
+
+        apply(set)
+    }
+
+    /// Computes the updates that turn `self` into `target`, without touching
+    /// the libcap-ng state, so they can be inspected or logged before being
+    /// passed to `update`.
+    ///
+    /// Updates are ordered by vector, in the order `CapState` declares them,
+    /// with the additions to a vector before its drops, each by capability
+    /// ID. Nothing is returned for capabilities present in both or neither.
+    pub fn minimal_diff_updates(&self, target: &CapState) -> Vec<CUpdate> {
+        let mut updates = Vec::new();
+        for ((which, now), (_, wanted)) in self.vectors().iter().zip(target.vectors().iter()) {
+            for (action, caps) in [
+                (Action::ADD, wanted.difference(*now)),
+                (Action::DROP, now.difference(*wanted)),
+            ] {
+                updates.extend(caps.iter().map(|capability| CUpdate {
+                    action,
+                    cap_type: *which,
+                    capability,
+                }));
+            }
+        }
+
+        updates
+    }
+
+    /// Returns every vector along with the type naming it.
+    fn vectors(&self) -> [(Type, CapabilitySet); 5] {
+        [
+            (Type::EFFECTIVE, self.effective),
+            (Type::PERMITTED, self.permitted),
+            (Type::INHERITABLE, self.inheritable),
+            (Type::BOUNDING_SET, self.bounding),
+            (Type::AMBIENT, self.ambient),
+        ]
+    }
+}
+
+impl From<ProcessCaps> for CapState {
+    fn from(caps: ProcessCaps) -> Self {
+        CapState {
+            effective: caps.effective,
+            permitted: caps.permitted,
+            inheritable: caps.inheritable,
+            bounding: caps.bounding,
+            ambient: caps.ambient,
+        }
+    }
+}
+
+impl fmt::Display for CapState {
+    /// Displays the effective, permitted and inheritable sets like `getpcaps`
+    /// does, see `CapState::to_getpcaps_string`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_getpcaps_string())
+    }
+}
+
+/// Checks that the target process holds exactly the capabilities in
+/// `expected`, e.g. to verify that a program didn't gain any through its file
+/// capabilities after exec, returning `Error::CapsMismatch` otherwise.
+///
+/// The previous libcap-ng state is restored before returning.
+pub fn assert_caps_eq(expected: &CapState) -> Result<()> {
+    let actual = CapState::capture()?;
+    if actual == *expected {
+        Ok(())
+    } else {
+        Err(Error::CapsMismatch {
+            expected: expected.clone(),
+            actual,
+        })
+    }
+}
+
+thread_local! {
+    // Like the libcap-ng state it mirrors, the cache is per thread.
+    static CAPS_CACHE: RefCell<Option<CapsCache>> = const { RefCell::new(None) };
+}
+
+/// A copy of the libcap-ng state, valid as long as the generation it was
+/// taken at is current.
+struct CapsCache {
+    generation: u64,
+    caps: Option<CapState>,
+}
+
+/// Loads the capabilities of the target process and starts caching the
+/// libcap-ng state for the calling thread, so `have_capability`, and the
+/// helpers built on it, answer from memory instead of calling into
+/// libcap-ng.
+///
+/// The cache is refreshed automatically the first time it's consulted after
+/// a function that modifies the libcap-ng state, like `update` or
+/// `get_caps_process`, so it always answers like libcap-ng would.
+pub fn enable_caps_cache() -> Result<()> {
+    get_caps_process()?;
+    CAPS_CACHE.with(|c| {
+        *c.borrow_mut() = Some(CapsCache {
+            generation: caps_generation(),
+            caps: Some(snapshot_state()),
+        })
+    });
+    Ok(())
+}
+
+/// Discards the cached capabilities, if caching is enabled, so they're read
+/// again from libcap-ng the next time they're consulted.
+pub fn invalidate_caps_cache() {
+    CAPS_CACHE.with(|c| {
+        if let Some(cache) = c.borrow_mut().as_mut() {
+            cache.caps = None;
+        }
+    });
+}
+
+/// Stops caching the libcap-ng state for the calling thread.
+pub fn disable_caps_cache() {
+    CAPS_CACHE.with(|c| *c.borrow_mut() = None);
+}
+
+/// Answers `have_capability` from the cache, refreshing it if needed, or
+/// returns `None` if caching is disabled or `which` isn't a cached vector.
+pub(crate) fn cached_capability(which: Type, capability: Capability) -> Option<bool> {
+    CAPS_CACHE.with(|c| {
+        let mut cache = c.borrow_mut();
+        let cache = cache.as_mut()?;
+        if cache.generation != caps_generation() {
+            cache.generation = caps_generation();
+            cache.caps = None;
+        }
+        let caps = cache.caps.get_or_insert_with(snapshot_state);
+
+        let set = match which {
+            Type::EFFECTIVE => caps.effective,
+            Type::PERMITTED => caps.permitted,
+            Type::INHERITABLE => caps.inheritable,
+            Type::BOUNDING_SET => caps.bounding,
+            Type::AMBIENT if supports_ambient() => caps.ambient,
+            _ => return None,
+        };
+        Some(set.contains(capability))
+    })
+}
+
+/// Copies the libcap-ng state, bypassing the cache.
+fn snapshot_state() -> CapState {
+    let set = |which| {
+        known_capabilities()
+            .filter(|&cap| have_capability_uncached(which, cap))
+            .collect()
+    };
+
+    CapState {
+        effective: set(Type::EFFECTIVE),
+        permitted: set(Type::PERMITTED),
+        inheritable: set(Type::INHERITABLE),
+        bounding: set(Type::BOUNDING_SET),
+        ambient: if supports_ambient() {
+            set(Type::AMBIENT)
+        } else {
+            CapabilitySet::empty()
+        },
+    }
+}
+
+/// Saves the capabilities of the target process in the `var` environment
+/// variable, so that a program can re-execute itself and restore them with
+/// `import_state_from_env`.
+///
+/// The variable holds the `CapState::to_bytes` format, hex-encoded. The
+/// previous libcap-ng state is restored before returning.
+pub fn export_state_to_env(var: &str) -> Result<()> {
+    let bytes = CapState::capture()?.to_bytes();
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    env::set_var(var, hex);
+
+    Ok(())
+}
+
+/// Reads the capabilities saved with `export_state_to_env` in the `var`
+/// environment variable and restores them with `CapState::restore`,
+/// returning them, or `None` if the variable isn't set.
+///
+/// The variable is left as is, so remove it before executing another
+/// program that shouldn't see it.
+pub fn import_state_from_env(var: &str) -> Result<Option<CapState>> {
+    let hex = match env::var(var) {
+        Ok(hex) => hex,
+        Err(env::VarError::NotPresent) => return Ok(None),
+        Err(env::VarError::NotUnicode(_)) => {
+            return Err(Error::ReadEnvironmentVariable(var.to_string()))
+        }
+    };
+
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return Err(Error::InvalidStateBytes);
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| Error::InvalidStateBytes))
+        .collect::<Result<Vec<u8>>>()?;
+
+    let state = CapState::from_bytes(&bytes)?;
+    state.restore()?;
+
+    Ok(Some(state))
+}
+
+/// Reads the capabilities of pid 1, which is the init process of the PID
+/// namespace the caller is in, e.g. to see what a container's init may do
+/// from inside the container.
+///
+/// The root ID only matters when writing file capabilities, so it doesn't
+/// affect this. The previous libcap-ng state, including the target pid and
+/// root ID, is restored before returning.
+pub fn namespace_init_caps() -> Result<CapState> {
+    with_saved_state(|| {
+        setpid(1);
+        get_caps_process()?;
+        Ok(CapState::from(ProcessCaps::from_state()))
+    })
+}
+
+/// Brings the `set` vectors of the target process in line with `desired`,
+/// updating only the capabilities that differ, and returns how many
+/// capability changes were made.
+///
+/// The libcap-ng state is loaded from the target process and left holding
+/// the result. Nothing is applied if no change is needed.
+pub fn reconcile(desired: &CapState, set: Set) -> Result<usize> {
+    get_caps_process()?;
+    let current = CapState::from(ProcessCaps::from_state());
+
+    let updates: Vec<CUpdate> = current
+        .minimal_diff_updates(desired)
+        .into_iter()
+        .filter(|u| match u.cap_type {
+            Type::BOUNDING_SET => set.contains(Set::BOUNDS),
+            Type::AMBIENT => set.contains(Set::AMBIENT),
+            _ => set.contains(Set::CAPS),
+        })
+        .collect();
+
+    let changes = updates.len();
+    if changes > 0 {
+        update(updates)?;
+        apply(set)?;
+    }
+
+    Ok(changes)
+}
+
+/// Reads the capabilities of the target process and returns only those that
+/// differ from `baseline`, as `(vector, capability, now present)` tuples,
+/// e.g. to log what changed without dumping every set.
+///
+/// Tuples are ordered by vector, in the order `CapState` declares them, then
+/// by capability ID. The previous libcap-ng state is restored before
+/// returning.
+pub fn caps_changed_since(baseline: &CapState) -> Result<Vec<(Type, Capability, bool)>> {
+    let current = CapState::capture()?;
+
+    let mut changes = Vec::new();
+    for ((which, now), (_, before)) in current.vectors().iter().zip(baseline.vectors().iter()) {
+        let toggled = now.difference(*before).union(before.difference(*now));
+        changes.extend(toggled.iter().map(|cap| (*which, cap, now.contains(cap))));
+    }
+
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::skip_unprivileged;
+
+    #[test]
+    fn cap_state_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        // Capabilities are per thread, so change them on a thread of our own.
+        std::thread::spawn(|| {
+            let state = CapState::capture().unwrap();
+            assert!(!state.permitted.is_empty());
+
+            let mut lowered = state.clone();
+            lowered.effective.remove(crate::cap!(SYS_ADMIN));
+            lowered.restore().unwrap();
+            assert_eq!(CapState::capture().unwrap(), lowered);
+            assert_ne!(lowered, state);
+
+            state.restore().unwrap();
+            assert_eq!(CapState::capture().unwrap(), state);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn assert_caps_eq_tests() {
+        let state = CapState::capture().unwrap();
+        assert_caps_eq(&state).unwrap();
+
+        let mut expected = state.clone();
+        expected.inheritable.insert(crate::cap!(KILL));
+        match assert_caps_eq(&expected) {
+            Err(Error::CapsMismatch {
+                expected: e,
+                actual: a,
+            }) => {
+                assert_eq!(e, expected);
+                assert_eq!(a, state);
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn minimal_diff_updates_tests() {
+        let from = CapState {
+            effective: [0, 5].iter().collect(),
+            permitted: [0, 5].iter().collect(),
+            bounding: [0, 5, 12].iter().collect(),
+            ..CapState::default()
+        };
+        let to = CapState {
+            effective: [5, 12].iter().collect(),
+            permitted: [0, 5].iter().collect(),
+            ambient: [5].iter().collect(),
+            ..CapState::default()
+        };
+        let cupdate = |action, cap_type, capability| CUpdate {
+            action,
+            cap_type,
+            capability,
+        };
+
+        assert_eq!(
+            from.minimal_diff_updates(&to),
+            vec![
+                cupdate(Action::ADD, Type::EFFECTIVE, 12),
+                cupdate(Action::DROP, Type::EFFECTIVE, 0),
+                cupdate(Action::DROP, Type::BOUNDING_SET, 0),
+                cupdate(Action::DROP, Type::BOUNDING_SET, 5),
+                cupdate(Action::DROP, Type::BOUNDING_SET, 12),
+                cupdate(Action::ADD, Type::AMBIENT, 5),
+            ]
+        );
+        assert!(from.minimal_diff_updates(&from).is_empty());
+        assert_eq!(
+            to.minimal_diff_updates(&from).len(),
+            from.minimal_diff_updates(&to).len()
+        );
+    }
+
+    #[test]
+    fn reconcile_tests() {
+        if skip_unprivileged() {
+            return;
+        }
+
+        // Capabilities are per thread, so change them on a thread of our own.
+        std::thread::spawn(|| {
+            let state = CapState::capture().unwrap();
+            assert_eq!(reconcile(&state, Set::BOTH).unwrap(), 0);
+
+            let mut desired = state.clone();
+            desired.effective.remove(crate::cap!(SYS_ADMIN));
+            desired.effective.remove(crate::cap!(NET_RAW));
+            // Not selected, so left alone.
+            desired.bounding.remove(crate::cap!(CHOWN));
+            assert_eq!(reconcile(&desired, Set::CAPS).unwrap(), 2);
+
+            let mut expected = desired.clone();
+            expected.bounding = state.bounding;
+            assert_eq!(CapState::capture().unwrap(), expected);
+
+            assert_eq!(reconcile(&state, Set::CAPS).unwrap(), 2);
+            assert_eq!(CapState::capture().unwrap(), state);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn namespace_init_caps_tests() {
+        let status = match std::fs::read_to_string("/proc/1/status") {
+            Ok(status) => status,
+            Err(_) => return,
+        };
+        let mask = |key: &str| {
+            status
+                .lines()
+                .find_map(|l| l.strip_prefix(key))
+                .map(|m| CapabilitySet::from_bits(u64::from_str_radix(m.trim(), 16).unwrap()))
+                .unwrap()
+        };
+
+        let caps = namespace_init_caps().unwrap();
+        assert_eq!(caps.effective, mask("CapEff:"));
+        assert_eq!(caps.permitted, mask("CapPrm:"));
+        assert_eq!(caps.inheritable, mask("CapInh:"));
+        assert_eq!(caps.bounding, mask("CapBnd:"));
+    }
+
+    #[test]
+    fn caps_changed_since_tests() {
+        let state = CapState::capture().unwrap();
+        assert!(caps_changed_since(&state).unwrap().is_empty());
+
+        let mut baseline = state.clone();
+        baseline.inheritable.insert(crate::cap!(KILL));
+        baseline.bounding.remove(crate::cap!(CHOWN));
+        let changes = caps_changed_since(&baseline).unwrap();
+
+        let mut expected = Vec::new();
+        if state.bounding.contains(crate::cap!(CHOWN)) {
+            expected.push((Type::BOUNDING_SET, crate::cap!(CHOWN), true));
+        }
+        if !state.inheritable.contains(crate::cap!(KILL)) {
+            expected.insert(0, (Type::INHERITABLE, crate::cap!(KILL), false));
+        }
+        assert_eq!(changes, expected);
+    }
+
+    #[test]
+    fn caps_cache_tests() {
+        // The cache is per thread, so use a thread of our own.
+        std::thread::spawn(|| {
+            let kill = crate::cap!(KILL);
+            enable_caps_cache().unwrap();
+            let expected = CapState::capture().unwrap();
+            assert_eq!(
+                crate::have_capability(Type::PERMITTED, kill),
+                expected.permitted.contains(kill)
+            );
+
+            // Mutations are picked up without invalidating explicitly.
+            clear(Set::BOTH);
+            assert!(!crate::have_capability(Type::PERMITTED, kill));
+            update(vec![CUpdate {
+                action: Action::ADD,
+                cap_type: Type::INHERITABLE,
+                capability: kill,
+            }])
+            .unwrap();
+            assert!(crate::have_capability(Type::INHERITABLE, kill));
+
+            invalidate_caps_cache();
+            assert!(crate::have_capability(Type::INHERITABLE, kill));
+            disable_caps_cache();
+            assert!(crate::have_capability(Type::INHERITABLE, kill));
+            assert!(CAPS_CACHE.with(|c| c.borrow().is_none()));
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn state_bytes_tests() {
+        let state = CapState {
+            effective: [0, 5].iter().collect(),
+            permitted: [0, 5, 13].iter().collect(),
+            inheritable: [13].iter().collect(),
+            // Unknown to the running kernel.
+            bounding: [0, 62, 63].iter().collect(),
+            ambient: CapabilitySet::empty(),
+        };
+        let bytes = state.to_bytes();
+        assert_eq!(&bytes[..5], b"CAPS\x01");
+        assert_eq!(bytes.len(), 45);
+        assert_eq!(CapState::from_bytes(&bytes).unwrap(), state);
+
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        for invalid in [&bytes[..44], &newer[..], b"CAPX\x01", &[]] {
+            match CapState::from_bytes(invalid) {
+                Err(Error::InvalidStateBytes) => (),
+                r => panic!("unexpected result: {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn state_env_tests() {
+        let var = format!("CAPNG_TEST_STATE_{}", std::process::id());
+        assert_eq!(import_state_from_env(&var).unwrap(), None);
+
+        env::set_var(&var, "CAPS");
+        match import_state_from_env(&var) {
+            Err(Error::InvalidStateBytes) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        if skip_unprivileged() {
+            env::remove_var(&var);
+            return;
+        }
+
+        // Capabilities are per thread, so change them on a thread of our own.
+        std::thread::spawn(move || {
+            let state = CapState::capture().unwrap();
+            export_state_to_env(&var).unwrap();
+            assert!(env::var(&var).unwrap().starts_with("4341505301"));
+
+            let mut lowered = state.clone();
+            lowered.effective.remove(crate::cap!(SYS_ADMIN));
+            lowered.restore().unwrap();
+
+            assert_eq!(import_state_from_env(&var).unwrap(), Some(state.clone()));
+            assert_eq!(CapState::capture().unwrap(), state);
+            env::remove_var(&var);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn getpcaps_format_tests() {
+        assert_eq!(CapState::default().to_string(), "=");
+
+        let state = CapState {
+            effective: [0, 5].iter().collect(),
+            permitted: [0, 5, 13].iter().collect(),
+            inheritable: [13, 1000].iter().collect(),
+            ..CapState::default()
+        };
+        assert_eq!(
+            state.to_getpcaps_string(),
+            "cap_chown,cap_kill+ep cap_net_raw+ip"
+        );
+        assert_eq!(
+            state.permitted.to_string(),
+            "cap_chown,cap_kill,cap_net_raw"
+        );
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright (C) 2020 Red Hat, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+//! Detection of libcap-ng features that not every version of the library
+//! provides.
+//!
+//! Symbols introduced after the oldest libcap-ng this crate supports aren't
+//! declared in the bindings, as binding them directly would prevent the
+//! program from even loading against an older library. Instead, they're
+//! looked up at runtime the first time they're needed.
+
+use self::symbols::{get_rootid_fn, set_rootid_fn};
+use std::fs;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{Capability, Error, Result};
+
+/// Value returned by `get_rootid` when no root ID has been set.
+pub const UNSET_ROOTID: i32 = -1;
+
+const KERNEL_LAST_CAP: &str = "/proc/sys/kernel/cap_last_cap";
+
+/// The cached result of `kernel_last_cap`, or `LAST_CAP_UNKNOWN`.
+static LAST_CAP: AtomicU32 = AtomicU32::new(LAST_CAP_UNKNOWN);
+const LAST_CAP_UNKNOWN: u32 = u32::MAX;
+
+#[cfg(not(capng_static_link))]
+mod symbols {
+    use std::os::raw::{c_char, c_int, c_void};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const UNRESOLVED: usize = 0;
+    const MISSING: usize = 1;
+
+    /// A libcap-ng symbol resolved at runtime, caching the result of the
+    /// lookup.
+    struct OptionalSymbol {
+        name: &'static [u8],
+        addr: AtomicUsize,
+    }
+
+    impl OptionalSymbol {
+        /// `name` must be NUL-terminated.
+        const fn new(name: &'static [u8]) -> Self {
+            OptionalSymbol {
+                name,
+                addr: AtomicUsize::new(UNRESOLVED),
+            }
+        }
+
+        fn get(&self) -> Option<*mut c_void> {
+            let mut addr = self.addr.load(Ordering::Relaxed);
+            if addr == UNRESOLVED {
+                // Safe because name is a NUL-terminated string and we only
+                // store the returned address.
+                let sym =
+                    unsafe { libc::dlsym(libc::RTLD_DEFAULT, self.name.as_ptr() as *const c_char) };
+                addr = if sym.is_null() { MISSING } else { sym as usize };
+                self.addr.store(addr, Ordering::Relaxed);
+            }
+
+            match addr {
+                MISSING => None,
+                addr => Some(addr as *mut c_void),
+            }
+        }
+
+        /// Forgets the result of the lookup, so the next use resolves the
+        /// symbol again.
+        fn reset(&self) {
+            self.addr.store(UNRESOLVED, Ordering::Relaxed);
+        }
+    }
+
+    // Both symbols were introduced in libcap-ng 0.8, along with ambient
+    // capabilities support.
+    static GET_ROOTID: OptionalSymbol = OptionalSymbol::new(b"capng_get_rootid\0");
+    static SET_ROOTID: OptionalSymbol = OptionalSymbol::new(b"capng_set_rootid\0");
+
+    pub(super) fn reset() {
+        GET_ROOTID.reset();
+        SET_ROOTID.reset();
+    }
+
+    pub(super) fn get_rootid_fn() -> Option<unsafe extern "C" fn() -> c_int> {
+        // Safe because the symbol, if present, has this signature.
+        GET_ROOTID.get().map(|sym| unsafe {
+            std::mem::transmute::<*mut c_void, unsafe extern "C" fn() -> c_int>(sym)
+        })
+    }
+
+    pub(super) fn set_rootid_fn() -> Option<unsafe extern "C" fn(c_int) -> c_int> {
+        // Safe because the symbol, if present, has this signature.
+        SET_ROOTID.get().map(|sym| unsafe {
+            std::mem::transmute::<*mut c_void, unsafe extern "C" fn(c_int) -> c_int>(sym)
+        })
+    }
+}
+
+// A statically linked libcap-ng is fixed at build time, and its symbols
+// aren't visible to dlsym, so bind them directly.
+#[cfg(capng_static_link)]
+mod symbols {
+    use std::os::raw::c_int;
+
+    pub(super) fn reset() {}
+
+    pub(super) fn get_rootid_fn() -> Option<unsafe extern "C" fn() -> c_int> {
+        Some(crate::bindings::capng_get_rootid)
+    }
+
+    pub(super) fn set_rootid_fn() -> Option<unsafe extern "C" fn(c_int) -> c_int> {
+        Some(crate::bindings::capng_set_rootid)
+    }
+}
+
+/// Drops the cached optional symbol lookups, see `reset_after_fork`.
+pub(crate) fn reset_cache() {
+    symbols::reset();
+}
+
+/// Frees a buffer allocated by libcap-ng, like the ones its print functions
+/// return. Null pointers are ignored, as `free` does.
+///
+/// libcap-ng doesn't export a deallocator, and allocates with the `malloc`
+/// it binds to through the global symbol scope. `libc::free` is resolved the
+/// same way, so it pairs with that `malloc` even when another allocator,
+/// like jemalloc or tcmalloc, is preloaded to interpose both, which looking
+/// `free` up in the library's own dependencies would get wrong.
+///
+/// # Safety
+///
+/// `buffer` must be null or allocated by libcap-ng with `malloc`, and not
+/// used anymore after this call.
+pub(crate) unsafe fn free_buffer(buffer: *mut c_void) {
+    if !buffer.is_null() {
+        libc::free(buffer);
+    }
+}
+
+/// Returns the highest capability ID supported by the running kernel, as
+/// read from `/proc/sys/kernel/cap_last_cap`.
+///
+/// This may be lower than the capabilities libcap-ng knows about when
+/// running on an older kernel. The value is read once and cached, as it
+/// can't change while the system is running.
+pub fn kernel_last_cap() -> Result<Capability> {
+    let cached = LAST_CAP.load(Ordering::Relaxed);
+    if cached != LAST_CAP_UNKNOWN {
+        return Ok(cached);
+    }
+
+    let last_cap = fs::read_to_string(KERNEL_LAST_CAP)
+        .ok()
+        .and_then(|s| s.trim().parse::<Capability>().ok())
+        .ok_or(Error::GetKernelLastCap)?;
+    LAST_CAP.store(last_cap, Ordering::Relaxed);
+
+    Ok(last_cap)
+}
+
+/// Returns whether the linked libcap-ng supports ambient capabilities.
+pub fn supports_ambient() -> bool {
+    get_rootid_fn().is_some()
+}
+
+/// Returns an error naming `feature` unless the linked libcap-ng supports
+/// ambient capabilities.
+pub(crate) fn check_ambient(feature: &'static str) -> Result<()> {
+    if supports_ambient() {
+        Ok(())
+    } else {
+        Err(Error::UnsupportedByLibrary(feature))
+    }
+}
+
+/// Gets the root ID used when writing file capabilities, or `UNSET_ROOTID`.
+pub fn get_rootid() -> Result<i32> {
+    let f = get_rootid_fn().ok_or(Error::UnsupportedByLibrary("capng_get_rootid"))?;
+
+    // Safe because this doesn't modify any local memory.
+    Ok(unsafe { f() })
+}
+
+/// Sets the root ID used when writing file capabilities, which makes them
+/// apply only within the user namespace whose root maps to `rootid`.
+pub fn set_rootid(rootid: i32) -> Result<()> {
+    let f = set_rootid_fn().ok_or(Error::UnsupportedByLibrary("capng_set_rootid"))?;
+
+    // Safe because this doesn't modify any local memory.
+    let ret = unsafe { f(rootid) };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(Error::SetRootId(rootid))
+    }
+}
+
+/// Returns the ID that root in the user namespace of the calling process
+/// maps to in the parent namespace, which is 0 in the initial namespace, or
+/// `None` if root isn't mapped.
+pub(crate) fn namespace_rootid() -> Option<i32> {
+    root_mapping(&fs::read_to_string("/proc/self/uid_map").ok()?)
+}
+
+/// Finds the ID that 0 maps to in a `uid_map` formatted `map`.
+fn root_mapping(map: &str) -> Option<i32> {
+    map.lines().find_map(|line| {
+        let mut fields = line.split_whitespace().map(|f| f.parse::<u32>().ok());
+        match (fields.next()??, fields.next()??, fields.next()??) {
+            (0, outside, count) if count > 0 => Some(outside as i32),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rootid_tests() {
+        if !supports_ambient() {
+            eprintln!("skipped: the linked libcap-ng doesn't support root IDs");
+            return;
+        }
+
+        // The root ID is part of the per-thread libcap-ng state, so start
+        // from a fresh one, and put it back once done, as libcap-ng refuses
+        // to set UNSET_ROOTID.
+        std::thread::spawn(|| {
+            crate::get_caps_process().unwrap();
+            assert_eq!(get_rootid().unwrap(), UNSET_ROOTID);
+            let state = crate::save_state().unwrap();
+
+            set_rootid(1000).unwrap();
+            assert_eq!(get_rootid().unwrap(), 1000);
+            assert!(set_rootid(-5).is_err());
+
+            crate::restore_state(state);
+            assert_eq!(get_rootid().unwrap(), UNSET_ROOTID);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn root_mapping_tests() {
+        assert_eq!(root_mapping("         0          0 4294967295\n"), Some(0));
+        assert_eq!(
+            root_mapping("      1000       1000          1\n         0     100000      1000\n"),
+            Some(100000)
+        );
+        assert_eq!(root_mapping("      1000       1000          1\n"), None);
+        assert_eq!(root_mapping(""), None);
+    }
+
+    #[test]
+    fn kernel_last_cap_tests() {
+        let expected: Capability = fs::read_to_string(KERNEL_LAST_CAP)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        assert_eq!(kernel_last_cap().unwrap(), expected);
+        assert_eq!(LAST_CAP.load(Ordering::Relaxed), expected);
+        assert_eq!(kernel_last_cap().unwrap(), expected);
+
+        assert!(crate::known_capabilities().all(|cap| cap <= expected));
+    }
+}